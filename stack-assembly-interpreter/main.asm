@@ -1,3 +1,6 @@
+String EQU 2000
+IsNeg EQU 3000
+
 ReadLine
 CALL
 
@@ -6,6 +9,7 @@ CALL
 
 ToHex
 CALL
+GETRV
 ;;; NOTE: stack = [std::end(String)]
 
 OutputHexReversed
@@ -14,36 +18,32 @@ CALL
 0
 HALT
 
-;;; NOTE: read line from stdin and save to :String as null terminated string
-:ReadLine
-    SETFP
-    String
-    LOAD                        ; p
+;;; NOTE: read line from stdin and save to String as null terminated string
+%proc ReadLine
+    String                      ; p
     IN                          ; p '1'
     DUP                         ; p '1' '1'
-    45                          ; p '1' '1' '-'
+    '-'                         ; p '1' '1' '-'
     SUB                         ; p '1' ('1' - '-')
-    PositiveNumber
+    .positive
     JNE
 
-    IsNeg
-    LOAD                        ; p '-' IsNeg
+    IsNeg                       ; p '-' IsNeg
     1
     SAVE
     DROP
     IN
-    LoopReadLine
+    .loop
     JMP
-    :PositiveNumber
-    IsNeg
-    LOAD                        ; p '1' IsNeg
+    :.positive
+    IsNeg                       ; p '1' IsNeg
     0
     SAVE
-    :LoopReadLine
+    :.loop
         DUP                     ; p '1' '1'
-        10
+        '\n'
         SUB                     ; p '1' '1'-'\n'
-        EndLoopReadLine
+        .end
         JEQ
         OVER                    ; p '1' p
         SWAP                    ; p p '1'
@@ -51,65 +51,59 @@ HALT
         1
         ADD                     ; p++
         IN                      ; p '1'
-        LoopReadLine
+        .loop
         JMP
-    :EndLoopReadLine
+    :.end
     DROP
     0
     SAVE
-    GETFP
-    RET
+%endproc
 
 ;;; rv = stoi(*String)
-:Stoi
-    SETFP
+%proc Stoi
 
-    String
-    LOAD                        ; p
+    String                      ; p
     LOAD                        ; '1'
     DUP
-    SkipEarlyReturn
+    .skip_early_return
     JNE
     0
     HALT
-    :SkipEarlyReturn
-    48
+    :.skip_early_return
+    '0'
     SUB                         ; 1
 
     String                      ; 1 p
-    LOAD
-    :LoopStoi
+    :.loop
         1
         ADD                     ; 1 p+1
         DUP                     ; 1 p+1 p+1
         LOAD                    ; 1 p+1 '2'
         DUP                     ; 1 p+1 '2' '2'
-        LoopStoiEnd
+        .end
         JEQ
-        48
+        '0'
         SUB                     ; 1 p+1 2
         ROT                     ; p+1 2 1
         10
         MUL                     ; p+1 2 10
         ADD                     ; p+1 12
         SWAP                    ; 12 p+1
-        LoopStoi
+        .loop
         JMP
-    :LoopStoiEnd                ; 1 p 0
+    :.end                       ; 1 p 0
     DROP2
     SETRV
-    GETFP
-    RET
+%endproc
 
-:ToHex
-    SETFP
+;;; rv = std::end(String), digits of rv are written to String in reverse order
+%proc ToHex
 
-    String
-    LOAD                        ; p
-    :LoopToHex
+    String                      ; p
+    :.loop
     GETRV                       ; p 123
     DUP                         ; p 123 123
-    ToHexRet                    ;
+    .ret                        ;
     JEQ                         ; p 0
     16
     MOD                         ; p 123%16
@@ -117,20 +111,20 @@ HALT
     DUP                         ; p digit digit
     10
     SUB                         ; p digit digit - 10
-    GreaterThanNineToHex
+    .greater_than_nine
     JGE
     ;; if 123%16 < 10
-    48
+    '0'
     ADD                         ; p '0'
-    EndIfToHex
+    .end_if
     JMP
-    :GreaterThanNineToHex
+    :.greater_than_nine
     ;; p digit
     10
     SUB                         ; digit - 10
-    65
+    'A'
     ADD                         ; p 'A'
-    :EndIfToHex
+    :.end_if
     SWAP                        ; 'A' p
     DUP                         ; 'A' p p
     ROT                         ; p p 'A'
@@ -141,64 +135,55 @@ HALT
     16
     DIV                         ; p+1 123/16
     SETRV                       ; p+1
-    LoopToHex
+    .loop
     JMP
 
-    :ToHexRet                   ; p 0
+    :.ret                       ; p 0
     DROP                        ; p
-    GETFP
-    RET
+    SETRV
+%endproc
 
-:OutputHexReversed              ; std::end(String)
-    SETFP
+%proc OutputHexReversed args=1  ; std::end(String)
+    arg0                        ; it
 
     IsNeg
     LOAD
-    LOAD
-    SkipOutputMinus
+    .skip_minus
     JEQ
-    45
+    '-'
     OUT
-    :SkipOutputMinus
-    48
+    :.skip_minus
+    '0'
     OUT
-    120
+    'x'
     OUT
 
     DUP                         ; it it
     String
-    LOAD
     SUB                         ; it it-String
-    ZeroOutputHexReversedRet
+    .zero
     JEQ
 
-    :LoopOutputHexReversed
+    :.loop
     1
     SUB                         ; it
-    String
-    LOAD                        ; it 2000
+    String                      ; it 2000
     SWAP                        ; 2000 it
     DUP                         ; 2000 it it
     ROT                         ; it it 2000
     SUB                         ; it it-2000
-    OutputHexReversedRet
+    .ret
     JLT
     ;;; it
     DUP
     LOAD                        ; it 'A'
     OUT                         ; it
-    LoopOutputHexReversed
+    .loop
     JMP
 
-    :ZeroOutputHexReversedRet
-    48
+    :.zero
+    '0'
     OUT
 
-    :OutputHexReversedRet
-    GETFP
-    RET
-
-:String
-2000
-:IsNeg
-3000
+    :.ret
+%endproc
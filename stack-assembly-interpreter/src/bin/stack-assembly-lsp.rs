@@ -0,0 +1,7 @@
+use anyhow::Result;
+
+use stack_assembly_interpreter::serve_lsp;
+
+fn main() -> Result<()> {
+    serve_lsp()
+}
@@ -1,29 +1,155 @@
 mod logic;
 mod models;
 
-use std::fs;
+use std::{fs, path::{Path, PathBuf}};
 
-use anyhow::{Context, Result};
-use beau_collector::BeauCollector as _;
+use anyhow::{bail, Context, Result};
 
-use logic::{assembly::{self, TextFile}, stdio::Stdio, vm::Executor};
-use models::{command::ReturnCode, vm::VM};
+use logic::{assembly::{self, Program, TextFile}, brainfuck, check, diagnostic::{self, Report}, disasm, format, forth, image, object, symbol_map, stdio::Stdio, tinyc, vm::Executor};
+use models::{command::{Instruction, ReturnCode}, error::CollectErrors as _, vm::VM};
 
-pub fn run(file_paths: &[String]) -> Result<ReturnCode> {
-    let files = file_paths.into_iter()
-                             .map(|path| -> Result<_> {
-                                 Ok(TextFile{
-                                     name: path.to_string(),
-                                     text: fs::read_to_string(path)
-                                         .context(format!("failed to read file: {path}"))?
-                                 })
-                             })
-                             .bcollect::<Vec<_>>();
+pub use logic::assembly::Options;
+pub use logic::lsp::serve as serve_lsp;
+pub use logic::lint::{describe as describe_lints, parse_option as parse_lint_option};
 
-    let instructions = assembly::assembly(&files?)?;
+fn read_files(file_paths: &[String]) -> Result<Vec<TextFile>> {
+    file_paths.iter()
+              .map(|path| -> Result<_> {
+                  Ok(TextFile{
+                      name: path.to_string(),
+                      text: fs::read_to_string(path)
+                          .context(format!("failed to read file: {path}"))?
+                  })
+              })
+              .collect_errors()
+}
+
+fn read_source(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+/// Renders error with source lines of positions it mentions.
+pub fn render_error(err: &anyhow::Error, color: bool) -> String {
+    diagnostic::render(&diagnostic::reports(err), &read_source, color)
+}
 
+fn execute(instructions: Vec<Instruction>) -> Result<ReturnCode> {
     let vm = VM::new(instructions);
     let mut executor = Executor{ io: &mut Stdio::new() };
 
     executor.execute(vm)
 }
+
+/// Assembles program, printing warnings and writing listing and symbol map if requested in `options`.
+fn assemble(file_paths: &[String], options: &Options) -> Result<Vec<Instruction>> {
+    let program = Program::preprocess(&read_files(file_paths)?, options)?;
+    for warning in program.warnings(options)? {
+        eprintln!("{}", diagnostic::render(&[Report::from(&warning)], &read_source, options.color));
+    }
+    if let Some(path) = &options.listing {
+        fs::write(path, program.listing()?)
+            .context(format!("failed to write file: {}", path.display()))?;
+    }
+    if let Some(path) = &options.symbol_map {
+        let symbols = program.symbol_map()?;
+        let text = match path.extension().is_some_and(|x| x == "json") {
+            true => symbol_map::to_json(&symbols),
+            false => symbol_map::to_text(&symbols),
+        };
+        fs::write(path, text).context(format!("failed to write file: {}", path.display()))?;
+    }
+    program.instructions()
+}
+
+pub fn run(file_paths: &[String], options: &Options) -> Result<ReturnCode> {
+    let instructions = assemble(file_paths, options)?;
+
+    execute(instructions)
+}
+
+/// Writes relocatable object next to each source file, replacing its extension with `.o`,
+/// or to `output` if single source file is given.
+pub fn assemble_objects(file_paths: &[String], options: &Options, output: Option<&str>) -> Result<()> {
+    if output.is_some() && file_paths.len() > 1 {
+        bail!("cannot write objects of {} source files to single output file", file_paths.len())
+    }
+    read_files(file_paths)?.iter()
+                           .map(|file| -> Result<()> {
+                               let object = assembly::assemble_object(file, options)?;
+                               let path = output.map_or_else(|| Path::new(&file.name).with_extension("o"), PathBuf::from);
+                               fs::write(&path, object::serialize(&object))
+                                   .context(format!("failed to write file: {}", path.display()))
+                           })
+                           .collect_errors()
+}
+
+/// Links relocatable objects into image at `output`, with source positions if `debug_info` is set.
+pub fn link(object_paths: &[String], output: &str, debug_info: bool) -> Result<()> {
+    let objects = object_paths.iter()
+                              .map(|path| -> Result<_> {
+                                  let bytes = fs::read(path).context(format!("failed to read file: {path}"))?;
+                                  object::deserialize(&bytes).context(format!("invalid object file: {path}"))
+                              })
+                              .collect_errors::<Vec<_>>()?;
+
+    let instructions = object::link(&objects)?;
+
+    fs::write(output, image::serialize(&instructions, debug_info)).context(format!("failed to write file: {output}"))
+}
+
+/// Assembles program into image at `output`, with source positions if `debug_info` is set.
+pub fn build(file_paths: &[String], options: &Options, output: &str, debug_info: bool) -> Result<()> {
+    let instructions = assemble(file_paths, options)?;
+
+    fs::write(output, image::serialize(&instructions, debug_info)).context(format!("failed to write file: {output}"))
+}
+
+pub fn exec(image_path: &str) -> Result<ReturnCode> {
+    let bytes = fs::read(image_path).context(format!("failed to read file: {image_path}"))?;
+    let instructions = image::deserialize(&bytes, image_path).context(format!("invalid image file: {image_path}"))?;
+
+    execute(instructions)
+}
+
+/// Compiles Forth program and runs it.
+pub fn run_forth(file_paths: &[String]) -> Result<ReturnCode> {
+    execute(forth::compile(&read_files(file_paths)?)?)
+}
+
+/// Compiles program in tiny C-like language and runs it.
+pub fn run_tinyc(file_paths: &[String]) -> Result<ReturnCode> {
+    execute(tinyc::compile(&read_files(file_paths)?)?)
+}
+
+/// Compiles Brainfuck program and runs it.
+pub fn run_brainfuck(file_paths: &[String]) -> Result<ReturnCode> {
+    execute(brainfuck::compile(&read_files(file_paths)?)?)
+}
+
+/// Disassembles program image, or text file with list of opcodes.
+pub fn disassemble(path: &str) -> Result<String> {
+    let bytes = fs::read(path).context(format!("failed to read file: {path}"))?;
+    let opcodes = match image::is_image(&bytes) {
+        true => image::deserialize(&bytes, path).context(format!("invalid image file: {path}"))?
+                                                 .iter()
+                                                 .map(|x| x.opcode)
+                                                 .collect(),
+        false => disasm::parse_opcodes(&String::from_utf8_lossy(&bytes)).context(format!("invalid opcodes file: {path}"))?,
+    };
+    Ok(disasm::disassemble(&opcodes))
+}
+
+/// Checks stack usage of program without running it.
+pub fn check_stack(file_paths: &[String], options: &Options) -> Result<()> {
+    check::check(&assemble(file_paths, options)?)
+}
+
+/// Formats source files in place.
+pub fn format_files(file_paths: &[String]) -> Result<()> {
+    read_files(file_paths)?.iter()
+                           .map(|file| -> Result<()> {
+                               let text = format::format_checked(&file.text, &file.name)?;
+                               fs::write(&file.name, text).context(format!("failed to write file: {}", file.name))
+                           })
+                           .collect_errors()
+}
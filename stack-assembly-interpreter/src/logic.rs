@@ -1,6 +1,29 @@
 pub mod tokenize;
+pub mod expression;
+pub mod include;
+pub mod macros;
+pub mod procedure;
+pub mod conditional;
 pub mod command;
 pub mod labels;
+pub mod control;
+pub mod visibility;
 pub mod assembly;
+pub mod binary;
+pub mod image;
+pub mod listing;
+pub mod symbol_map;
+pub mod disasm;
+pub mod format;
+pub mod diagnostic;
+pub mod analysis;
+pub mod lsp;
+pub mod check;
+pub mod cfg;
+pub mod lint;
+pub mod object;
+pub mod forth;
+pub mod tinyc;
+pub mod brainfuck;
 pub mod vm;
 pub mod stdio;
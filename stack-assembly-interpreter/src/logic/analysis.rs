@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use anyhow::Error;
+
+use crate::models::command::Opcode;
+use crate::models::token::{Position, Token};
+use super::assembly::{self, Options, Program, TextFile};
+use super::command::COMMANDS;
+use super::diagnostic;
+use super::labels;
+use super::tokenize::split_line;
+use super::visibility::source_name;
+
+/// Error message attached to the source token it points at.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    pub position: Position,
+    pub length: usize,
+    pub message: String,
+}
+
+/// Knowledge about program being edited, built from its source files.
+pub struct Analysis {
+    files: Vec<TextFile>,
+    /// Preprocessed tokens of files without errors, names are resolved as in assembly.
+    tokens: Vec<Token>,
+    labels: HashMap<String, Opcode>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Returns positions of errors with their messages including causes and related positions, errors without position are skipped.
+/// Errors inside macro body are shown at invocation site.
+fn source_errors(err: &Error) -> Vec<(Position, String)> {
+    diagnostic::reports(err).into_iter()
+                            .filter_map(|report| {
+                                let message = report.labels
+                                                    .iter()
+                                                    .fold(report.message, |message, (pos, label)| format!("{message}\n{label}: {pos}"));
+                                Some((Position{expansion: None, ..report.position?}, message))
+                            })
+                            .collect()
+}
+
+const MAX_RECOVERY_ATTEMPTS: usize = 8;
+
+/// Preprocesses file, replacing tokens with errors by spaces until it succeeds,
+/// so that the rest of the file can still be navigated.
+fn preprocess_recovering(file: &TextFile, options: &Options) -> Vec<Token> {
+    let mut lines: Vec<String> = file.text.lines().map(str::to_string).collect();
+    for _ in 0..=MAX_RECOVERY_ATTEMPTS {
+        let text = TextFile{name: file.name.clone(), text: lines.join("\n")};
+        let errors = match assembly::preprocess(&text, options) {
+            Ok(tokens) => return tokens,
+            Err(err) => source_errors(&err),
+        };
+        for (pos, _) in errors.iter().filter(|(pos, _)| pos.filename == file.name) {
+            let Some(line) = lines.get_mut(pos.line.wrapping_sub(1)) else {
+                continue
+            };
+            let Some((column, text)) = split_line(line).0.into_iter().find(|(column, _)| *column == pos.column) else {
+                continue
+            };
+            let len = text.chars().count();
+            *line = line.chars()
+                        .enumerate()
+                        .map(|(i, c)| if (column-1..column-1+len).contains(&i) { ' ' } else { c })
+                        .collect();
+        }
+    }
+    vec![]
+}
+
+fn name_matches(qualified: &str, name: &str) -> bool {
+    qualified == name || qualified.strip_suffix(name).is_some_and(|x| x.ends_with("::") || name.starts_with('.'))
+}
+
+impl Analysis {
+    pub fn new(files: Vec<TextFile>, options: &Options) -> Self {
+        let errors = match Program::preprocess(&files, options).and_then(|program| program.instructions()) {
+            Ok(_) => vec![],
+            Err(err) => source_errors(&err),
+        };
+        let tokens: Vec<Token> = files.iter()
+                                      .flat_map(|file| preprocess_recovering(file, options))
+                                      .collect();
+        let labels = labels::get_labels(&tokens).map(|labels| labels.into_iter()
+                                                                    .map(|(name, value)| (name.to_string(), value))
+                                                                    .collect())
+                                                .unwrap_or_default();
+        let mut res = Self{files, tokens, labels, diagnostics: vec![]};
+        res.diagnostics = errors.into_iter()
+                                .map(|(position, message)| Diagnostic{
+                                    length: res.source_token(&position).map_or(1, |(_, text)| text.chars().count()),
+                                    position,
+                                    message,
+                                })
+                                .collect();
+        res
+    }
+
+    /// Returns source token covering `pos` with its starting column.
+    fn source_token(&self, pos: &Position) -> Option<(usize, &str)> {
+        let file = self.files.iter().find(|x| x.name == pos.filename)?;
+        let line = file.text.lines().nth(pos.line.checked_sub(1)?)?;
+        split_line(line).0.into_iter()
+                          .find(|(column, text)| (*column..column + text.chars().count().max(1)).contains(&pos.column))
+    }
+
+    /// Returns resolved name of symbol at `pos`.
+    fn symbol_at(&self, pos: &Position) -> Option<String> {
+        let (column, text) = self.source_token(pos)?;
+        let token = self.tokens.iter()
+                               .find(|x| x.position() == &Position{column, ..pos.clone()})?;
+        match token {
+            Token::Declaration(name, _) | Token::Ident(name, _) | Token::Constant(name, _, _) => Some(name.clone()),
+            Token::Expression(expression, _) => {
+                let offset = pos.column - column;
+                let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+                let chars = text.chars().collect::<Vec<_>>();
+                let start = (0..=offset).rev().take_while(|i| chars.get(*i).is_some_and(|c| is_word(*c))).last()?;
+                let end = (offset..chars.len()).find(|i| !is_word(chars[*i])).unwrap_or(chars.len());
+                let word = chars[start..end].iter().collect::<String>();
+                expression.symbols()
+                          .into_iter()
+                          .find(|x| name_matches(x, &word))
+                          .cloned()
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns declaration position of label or constant at `pos`.
+    pub fn definition(&self, pos: &Position) -> Option<Position> {
+        let name = self.symbol_at(pos)?;
+        self.tokens.iter()
+                   .find_map(|x| match x {
+                       Token::Declaration(decl, decl_pos) | Token::Constant(decl, _, decl_pos) if *decl == name => Some(decl_pos.clone()),
+                       _ => None,
+                   })
+    }
+
+    /// Returns positions of declaration and all uses of label or constant at `pos`.
+    /// Uses produced by macro expansion are reported at invocation site.
+    pub fn references(&self, pos: &Position) -> Vec<Position> {
+        let Some(name) = self.symbol_at(pos) else {
+            return vec![]
+        };
+        let mut res: Vec<Position> = vec![];
+        for token in &self.tokens {
+            let found = match token {
+                Token::Declaration(x, _) | Token::Ident(x, _) | Token::Constant(x, _, _) => *x == name,
+                Token::Expression(expression, _) => expression.symbols().contains(&&name),
+                _ => false,
+            };
+            let pos = Position{expansion: None, ..token.position().clone()};
+            if found && !res.contains(&pos) {
+                res.push(pos);
+            }
+        }
+        res
+    }
+
+    /// Describes symbol at `pos`: address of label, value of constant or stack effect of mnemonic.
+    pub fn hover(&self, pos: &Position) -> Option<String> {
+        let name = self.symbol_at(pos)?;
+        if let Some(command) = COMMANDS.iter().flatten().find(|x| x.mnemonics.contains(&name.as_str())) {
+            return Some(format!("{name} ( {} )", command.effect))
+        }
+        let value = self.labels.get(&name)?;
+        let is_constant = self.tokens.iter().any(|x| matches!(x, Token::Constant(decl, _, _) if *decl == name));
+        let name = source_name(&name);
+        Some(match is_constant {
+            true => format!("{name} EQU {value}"),
+            false => format!("{name} at address {value}"),
+        })
+    }
+
+    /// Returns mnemonics with their stack effects.
+    pub fn completions() -> Vec<(&'static str, &'static str)> {
+        COMMANDS.iter()
+                .flatten()
+                .flat_map(|x| x.mnemonics.iter().map(|mnemonic| (*mnemonic, x.effect)))
+                .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analysis(files: &[(&str, &str)]) -> Analysis {
+        let files = files.iter()
+                         .map(|(name, text)| TextFile{name: name.to_string(), text: text.to_string()})
+                         .collect();
+        Analysis::new(files, &Options::default())
+    }
+
+    fn pos(filename: &str, line: usize, column: usize) -> Position {
+        Position{filename: filename.to_string(), line, column, expansion: None}
+    }
+
+    #[test]
+    fn reports_diagnostics_from_all_files() {
+        let got = analysis(&[("main", "%global Main\n:Main ADD Loop"), ("lib", "\n  'ab'")]);
+
+        assert_eq!(got.diagnostics, vec![
+            Diagnostic{position: pos("main", 2, 11), length: 4, message: "undefined ident: \"Loop\"".to_string()},
+            Diagnostic{position: pos("lib", 2, 3), length: 4, message: "failed to tokenize char: \"'ab'\": char literal must contain exactly one character".to_string()},
+        ]);
+    }
+
+    #[test]
+    fn reports_errors_of_whole_assembly() {
+        let got = analysis(&[("main", ":Main\n  (Main*4611686018427387904)")]);
+
+        assert_eq!(got.diagnostics, vec![
+            Diagnostic{position: pos("main", 2, 3), length: 26, message: "overflow in expression".to_string()},
+        ]);
+    }
+
+    #[test]
+    fn reports_labels_declared_in_several_files() {
+        let got = analysis(&[("main", "%global Main\n:Main"), ("lib", "%global Main\n  :Main")]);
+
+        assert_eq!(got.diagnostics, vec![
+            Diagnostic{position: pos("lib", 2, 3), length: 5, message: "label declared twice: Main\nfirst declared here: main:2:1".to_string()},
+        ]);
+    }
+
+    #[test]
+    fn finds_definition_and_references_of_labels() {
+        let got = analysis(&[
+            ("main", "%extern Print\n:Main Print CALL\n:.loop .loop JMP (Main+1)"),
+            ("lib", "%global Print\n:Print RET"),
+        ]);
+
+        assert_eq!(got.definition(&pos("main", 2, 8)), Some(pos("lib", 2, 1)));
+        assert_eq!(got.definition(&pos("main", 3, 9)), Some(pos("main", 3, 1)));
+        assert_eq!(got.definition(&pos("main", 3, 20)), Some(pos("main", 2, 1)));
+        assert_eq!(got.references(&pos("lib", 2, 3)), vec![pos("main", 2, 7), pos("lib", 2, 1)]);
+        assert_eq!(got.references(&pos("main", 2, 2)), vec![pos("main", 2, 1), pos("main", 3, 18)]);
+    }
+
+    #[test]
+    fn describes_symbols_on_hover() {
+        let got = analysis(&[("main", "Size EQU 3\n:Main Size DUP Main")]);
+
+        assert_eq!(got.hover(&pos("main", 2, 8)).as_deref(), Some("Size EQU 3"));
+        assert_eq!(got.hover(&pos("main", 2, 13)).as_deref(), Some("DUP ( x -- x x )"));
+        assert_eq!(got.hover(&pos("main", 2, 17)).as_deref(), Some("Main at address 256"));
+        assert_eq!(got.hover(&pos("main", 1, 7)), None);
+    }
+
+    #[test]
+    fn navigates_file_with_errors() {
+        let got = analysis(&[("main", ":Main Main JMP Foo 'ab'\n.x")]);
+
+        assert_eq!(got.diagnostics.len(), 1);
+        assert_eq!(got.definition(&pos("main", 1, 7)), Some(pos("main", 1, 1)));
+        assert_eq!(got.hover(&pos("main", 1, 12)).as_deref(), Some("JMP ( addr -- )"));
+    }
+}
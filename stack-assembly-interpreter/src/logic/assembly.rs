@@ -1,28 +1,59 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, io, path::{Path, PathBuf}};
 
-use anyhow::{anyhow, Result};
-use beau_collector::BeauCollector as _;
+use anyhow::Result;
 
+use crate::models::error::{CollectErrors as _, error_at};
 use crate::models::token::Token;
 use crate::models::command::{Opcode, Instruction};
+use crate::models::object::Object;
+use crate::models::lint::{Level, Warning};
+use super::include::{self, FsLoader, SourceLoader};
 use super::tokenize;
+use super::expression;
+use super::macros;
+use super::procedure;
+use super::conditional;
 use super::labels;
+use super::control;
+use super::visibility;
+use super::object;
+use super::listing;
+use super::lint::{self, Pragma};
+use super::symbol_map::{self, SymbolInfo};
 
 fn generate_instructions(tokens: &[Token], labels: HashMap<&str, Opcode>) -> Result<Vec<Instruction>> {
     tokens.iter()
-          .filter_map(|x| match x {
-            Token::Declaration(_, _) => None,
-            _ => Some(x),
-          })
+          .filter(|x| !matches!(x, Token::Declaration(_, _) | Token::Constant(_, _, _)))
           .map(|x| match x {
             Token::Ident(i, pos) => labels.get(i.as_str())
                                           .copied()
                                           .map(|opcode| Instruction{opcode, token: x.clone()})
-                                          .ok_or_else(|| anyhow!("{pos}: undefined ident: \"{i}\"")),
+                                          .ok_or_else(|| error_at!(pos, "undefined ident: \"{}\"", visibility::source_name(i))),
             Token::Integer(i, _) => Ok(Instruction{opcode: *i, token: x.clone()}),
-            Token::Declaration(_, pos) => Err(anyhow!("{pos}: didn't expect declaration here")),
+            Token::Expression(i, pos) => expression::evaluate(i, &labels)
+                                                    .map(|opcode| Instruction{opcode, token: x.clone()})
+                                                    .map_err(|err| error_at!(pos, "{err}")),
+            Token::Declaration(_, pos) => Err(error_at!(pos, "didn't expect declaration here")),
+            Token::Directive(i, pos) => Err(error_at!(pos, "unexpected directive: %{i}")),
+            Token::String(_, pos) => Err(error_at!(pos, "didn't expect string here")),
+            Token::Constant(_, _, pos) => Err(error_at!(pos, "didn't expect constant definition here")),
           })
-          .bcollect()
+          .collect_errors()
+}
+
+/// Replaces string literals with integer per code point.
+fn expand_strings(tokens: Vec<Token>) -> Result<Vec<Token>> {
+    let mut res = vec![];
+    for token in tokens {
+        match token {
+            Token::String(literal, pos) => res.extend(
+                tokenize::decode_string(&literal)?.into_iter()
+                                                  .map(|(c, offset)| Token::Integer(c, pos.shifted(offset)))
+            ),
+            _ => res.push(token),
+        }
+    }
+    Ok(res)
 }
 
 pub struct TextFile {
@@ -30,15 +61,117 @@ pub struct TextFile {
     pub text: String,
 }
 
-pub fn assembly(files: &[TextFile]) -> Result<Vec<Instruction>> {
-    let tokens_by_file: Result<Vec<Vec<Token>>> = files.iter()
-                                                       .map(|file| tokenize::tokenize(&file.text, &file.name))
-                                                       .bcollect::<Vec<_>>();
-    let tokens: Vec<Token> = tokens_by_file?.into_iter()
-                                            .flatten()
-                                            .collect();
-    let labels = labels::get_labels(&tokens)?;
-    generate_instructions(&tokens, labels)
+#[derive(Default)]
+pub struct Options {
+    pub include_dirs: Vec<PathBuf>,
+    /// Where to write program listing, if set.
+    pub listing: Option<PathBuf>,
+    /// Where to write symbol map, as JSON if path has `.json` extension and `nm`-like text otherwise.
+    pub symbol_map: Option<PathBuf>,
+    /// Lint levels given in command line, later ones take precedence.
+    pub lint_levels: Vec<(String, Level)>,
+    /// Symbols given in command line for conditional assembly.
+    pub defines: Vec<(String, i64)>,
+    /// Whether to color printed warnings.
+    pub color: bool,
+}
+
+/// Loads files from disk, keeping their text for listing.
+#[derive(Default)]
+struct RecordingLoader {
+    sources: RefCell<HashMap<String, String>>,
+}
+
+impl SourceLoader for RecordingLoader {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        let text = FsLoader.load(path)?;
+        self.sources.borrow_mut().insert(path.to_string_lossy().into_owned(), text.clone());
+        Ok(text)
+    }
+}
+
+/// Translates single file into tokens ready for address assignment, collecting lint pragmas.
+/// `base` is address of first instruction of file, if it's known.
+fn preprocess_with_pragmas(file: &TextFile, options: &Options, loader: &dyn SourceLoader, base: Option<Opcode>) -> Result<(Vec<Token>, Vec<Pragma>)> {
+    let tokens = include::tokenize(&file.text, &file.name, &options.include_dirs, loader)?;
+    let (tokens, pragmas) = lint::collect_pragmas(procedure::expand(macros::expand(&tokens)?)?)?;
+    let tokens = expand_strings(tokens)?;
+    let tokens = control::lower(labels::resolve_local_labels(labels::fold_constants(tokens)?)?)?;
+    let tokens = conditional::resolve(tokens, &options.defines, base)?;
+    Ok((visibility::resolve(tokens, &file.name)?, pragmas))
+}
+
+/// Translates single file into tokens ready for address assignment.
+/// File is assembled separately, so its labels can't be used in `%if`.
+pub fn preprocess(file: &TextFile, options: &Options) -> Result<Vec<Token>> {
+    Ok(preprocess_with_pragmas(file, options, &FsLoader, None)?.0)
+}
+
+pub fn assemble_object(file: &TextFile, options: &Options) -> Result<Object> {
+    object::assemble(&preprocess(file, options)?)
+}
+
+/// Tokens of all files preprocessed once, from which instructions, warnings, listing and symbol map are derived.
+pub struct Program {
+    tokens: Vec<Token>,
+    pragmas: Vec<Pragma>,
+    /// Text of every file by name, including included ones.
+    sources: HashMap<String, String>,
+}
+
+impl Program {
+    pub fn preprocess(files: &[TextFile], options: &Options) -> Result<Program> {
+        let loader = RecordingLoader::default();
+        let mut base = 256;
+        let preprocessed = files.iter()
+                                .map(|file| {
+                                    let res = preprocess_with_pragmas(file, options, &loader, Some(base));
+                                    if let Ok((tokens, _)) = &res {
+                                        base += tokens.iter().filter(|x| !matches!(x, Token::Declaration(_, _) | Token::Constant(_, _, _))).count() as Opcode;
+                                    }
+                                    res
+                                })
+                                .collect_errors::<Vec<_>>()?;
+        let (tokens, pragmas): (Vec<Vec<Token>>, Vec<Vec<Pragma>>) = preprocessed.into_iter().unzip();
+        let mut sources = loader.sources.into_inner();
+        sources.extend(files.iter().map(|file| (file.name.clone(), file.text.clone())));
+        Ok(Program{tokens: tokens.concat(), pragmas: pragmas.concat(), sources})
+    }
+
+    pub fn instructions(&self) -> Result<Vec<Instruction>> {
+        assemble_tokens(&self.tokens)
+    }
+
+    /// Runs lints over program, see [`lint::check`].
+    /// Fails if any warning is denied.
+    pub fn warnings(&self, options: &Options) -> Result<Vec<Warning>> {
+        let labels = labels::get_labels(&self.tokens)?;
+        let instructions = generate_instructions(&self.tokens, labels.clone())?;
+        let warnings = lint::check(&self.tokens, &instructions, &labels);
+        lint::apply_levels(warnings, &self.pragmas, &options.lint_levels)
+    }
+
+    /// Renders program listing, see [`listing::listing`].
+    /// Files missing from sources are listed without source text.
+    pub fn listing(&self) -> Result<String> {
+        Ok(listing::listing(&self.tokens, &self.instructions()?, &self.sources))
+    }
+
+    /// Describes program symbols, see [`symbol_map::collect`].
+    pub fn symbol_map(&self) -> Result<Vec<SymbolInfo>> {
+        Ok(symbol_map::collect(&self.tokens, &labels::get_labels(&self.tokens)?))
+    }
+}
+
+/// Assigns addresses to preprocessed tokens and generates instructions.
+pub fn assemble_tokens(tokens: &[Token]) -> Result<Vec<Instruction>> {
+    generate_instructions(tokens, labels::get_labels(tokens)?)
+}
+
+/// Preprocesses and assembles program in one step.
+#[cfg(test)]
+pub fn assembly(files: &[TextFile], options: &Options) -> Result<Vec<Instruction>> {
+    Program::preprocess(files, options)?.instructions()
 }
 
 #[cfg(test)]
@@ -53,7 +186,7 @@ _Loop :a1 HALT _Read_number_ _- _ a1 ; a1 == 260
 1234 PROGRAM_SIZE
 :_Loop :_Read_number_ :_- ; _Loop == _Read_number_ == _- == 268");
 
-      let got = assembly(&[TextFile{name: "test".to_owned(), text}]).unwrap()
+      let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default()).unwrap()
                                                          .iter()
                                                          .map(|x| x.opcode)
                                                          .collect::<Vec<_>>();
@@ -65,7 +198,7 @@ _Loop :a1 HALT _Read_number_ _- _ a1 ; a1 == 260
     fn translates_hello_world() {
         let text = String::from("72 OUT 101 OUT 108 OUT 108 OUT 111 OUT 33 OUT 0 HALT");
 
-      let got = assembly(&[TextFile{name: "test".to_owned(), text}]).unwrap()
+      let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default()).unwrap()
                                                          .iter()
                                                          .map(|x| x.opcode)
                                                          .collect::<Vec<_>>();
@@ -77,7 +210,7 @@ _Loop :a1 HALT _Read_number_ _- _ a1 ; a1 == 260
     fn translates_commands() {
         let text = String::from("72 0 ADD");
 
-      let got = assembly(&[TextFile{name: "test".to_owned(), text}]).unwrap()
+      let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default()).unwrap()
                                                          .iter()
                                                          .map(|x| x.opcode)
                                                          .collect::<Vec<_>>();
@@ -89,7 +222,7 @@ _Loop :a1 HALT _Read_number_ _- _ a1 ; a1 == 260
     fn translates_labels() {
         let text = String::from("72 :a a 123 a");
 
-      let got = assembly(&[TextFile{name: "test".to_owned(), text}]).unwrap()
+      let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default()).unwrap()
                                                          .iter()
                                                          .map(|x| x.opcode)
                                                          .collect::<Vec<_>>();
@@ -101,8 +234,119 @@ _Loop :a1 HALT _Read_number_ _- _ a1 ; a1 == 260
     fn error_on_undefined_ident() {
         let text = String::from("72 a 123");
 
-        let got = assembly(&[TextFile{name: "test".to_owned(), text}]);
+        let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default());
 
         assert_eq!(got.unwrap_err().to_string(), "test:1:4: undefined ident: \"a\"");
     }
+
+    #[test]
+    fn translates_char_and_string_literals() {
+        let text = String::from("'-' \"a\\n\" c\"b\" :End");
+
+        let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default()).unwrap()
+                                                         .iter()
+                                                         .map(|x| (x.opcode, x.token.position().column))
+                                                         .collect::<Vec<_>>();
+
+        assert_eq!(got, vec![(45, 1), (97, 6), (10, 7), (98, 13), (0, 14)]);
+    }
+
+    #[test]
+    fn translates_constants() {
+        let text = String::from("Buffer EQU 0x100 :Start Buffer LOAD PROGRAM_SIZE Start");
+
+        let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default()).unwrap()
+                                                         .iter()
+                                                         .map(|x| x.opcode)
+                                                         .collect::<Vec<_>>();
+
+        assert_eq!(got, vec![256, -35, 260, 256]);
+    }
+
+    #[test]
+    fn translates_expressions() {
+        let text = String::from("Buffer EQU 2000 Index EQU 3
+:Start Buffer+3 Start+Index*2 ( End - Start ) PROGRAM_SIZE+100 :End");
+
+        let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default()).unwrap()
+                                                         .iter()
+                                                         .map(|x| x.opcode)
+                                                         .collect::<Vec<_>>();
+
+        assert_eq!(got, vec![2003, 262, 4, 360]);
+    }
+
+    #[test]
+    fn error_on_undefined_symbol_in_expression() {
+        let text = String::from("1 Buffer+3");
+
+        let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default());
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:3: undefined symbol in expression: \"Buffer\"");
+    }
+
+    #[test]
+    fn links_files_through_exported_symbols() {
+        let files = [
+            TextFile{name: "main".to_owned(), text: String::from("%extern Print\n:Loop Print CALL Loop")},
+            TextFile{name: "lib".to_owned(), text: String::from("%global Print\n:Print :Loop OUT Loop RET")},
+        ];
+
+        let got = assembly(&files, &Options::default()).unwrap()
+                                                    .iter()
+                                                    .map(|x| x.opcode)
+                                                    .collect::<Vec<_>>();
+
+        assert_eq!(got, vec![259, -31, 256, -44, 259, -13]);
+    }
+
+    #[test]
+    fn error_on_reference_to_private_symbol_of_other_file() {
+        let files = [
+            TextFile{name: "main".to_owned(), text: String::from("%extern Print\nPrint CALL Helper")},
+            TextFile{name: "lib".to_owned(), text: String::from(":Print :Helper RET")},
+        ];
+
+        let got = assembly(&files, &Options::default());
+
+        assert_eq!(got.unwrap_err().to_string(), "main:2:12: undefined ident: \"Helper\"");
+    }
+
+    #[test]
+    fn errors_show_source_names_of_private_symbols() {
+        let text = String::from(":Main Size\n:Main .a JMP Size EQU 1 :.a\nSize EQU 2");
+
+        let got = assembly(&[TextFile{name: "main".to_owned(), text}], &Options::default());
+
+        assert_eq!(got.unwrap_err().to_string(), "main:2:1: label declared twice: Main
+    first declared here: main:1:1
+main:3:1: constant defined twice: Size
+    first declared here: main:2:14");
+    }
+
+    #[test]
+    fn error_in_macro_points_to_invocation() {
+        let text = String::from("%macro LEAVE\nGETFP RETURN\n%endm\n:Main LEAVE");
+
+        let got = assembly(&[TextFile{name: "test".to_owned(), text}], &Options::default());
+
+        assert_eq!(got.unwrap_err().to_string(), "test:4:7 (in macro body at test:2:7): undefined ident: \"RETURN\"");
+    }
+
+    #[test]
+    fn conditions_see_label_addresses_after_preceding_files() {
+        let files = [
+            TextFile{name: "main".to_owned(), text: String::from("%extern Lib
+Lib CALL HALT")},
+            TextFile{name: "lib".to_owned(), text: String::from("%global Lib
+:Lib %if (Lib-259) 1 %else 2 %endif RET")},
+        ];
+
+        let got = assembly(&files, &Options::default()).unwrap()
+                                                    .iter()
+                                                    .map(|x| x.opcode)
+                                                    .collect::<Vec<_>>();
+
+        assert_eq!(got, vec![259, -31, -37, 2, -13]);
+    }
 }
@@ -0,0 +1,284 @@
+use anyhow::{bail, Result};
+
+use crate::models::expression::{BinaryOperator, Expression};
+use crate::models::token::{Position, Token};
+
+/// Limit on nesting of expressions and positions, so that crafted file can't overflow stack.
+const MAX_NESTING_DEPTH: usize = 256;
+
+/// Little-endian encoder for on-disk formats.
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new(magic: &[u8; 4], version: u32) -> Self {
+        let mut bytes = magic.to_vec();
+        bytes.extend(version.to_le_bytes());
+        Self { bytes }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    pub fn i64(&mut self, x: i64) {
+        self.bytes.extend(x.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, x: usize) {
+        self.bytes.extend((x as u64).to_le_bytes());
+    }
+
+    pub fn str(&mut self, x: &str) {
+        self.usize(x.len());
+        self.bytes.extend(x.as_bytes());
+    }
+
+    pub fn position(&mut self, pos: &Position) {
+        self.str(&pos.filename);
+        self.usize(pos.line);
+        self.usize(pos.column);
+        match &pos.expansion {
+            Some(body) => {
+                self.u8(1);
+                self.position(body);
+            },
+            None => self.u8(0),
+        }
+    }
+
+    pub fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Integer(i) => {
+                self.u8(0);
+                self.i64(*i);
+            },
+            Expression::Symbol(name) => {
+                self.u8(1);
+                self.str(name);
+            },
+            Expression::Negate(x) => {
+                self.u8(2);
+                self.expression(x);
+            },
+            Expression::Binary(op, x, y) => {
+                self.u8(3);
+                self.u8(*op as u8);
+                self.expression(x);
+                self.expression(y);
+            },
+        }
+    }
+
+    pub fn token(&mut self, token: &Token) {
+        match token {
+            Token::Integer(i, _) => {
+                self.u8(0);
+                self.i64(*i);
+            },
+            Token::Declaration(i, _) => {
+                self.u8(1);
+                self.str(i);
+            },
+            Token::Ident(i, _) => {
+                self.u8(2);
+                self.str(i);
+            },
+            Token::Directive(i, _) => {
+                self.u8(3);
+                self.str(i);
+            },
+            Token::String(i, _) => {
+                self.u8(4);
+                self.str(i);
+            },
+            Token::Constant(i, value, _) => {
+                self.u8(5);
+                self.str(i);
+                self.i64(*value);
+            },
+            Token::Expression(i, _) => {
+                self.u8(6);
+                self.expression(i);
+            },
+        }
+        self.position(token.position());
+    }
+}
+
+/// Decoder for data produced by [`Writer`].
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], magic: &[u8; 4], version: u32) -> Result<Self> {
+        let mut res = Self { bytes };
+        if res.take(4)? != magic {
+            bail!("invalid file format")
+        }
+        let got = u32::from_le_bytes(res.take(4)?.try_into()?);
+        if got != version {
+            bail!("unsupported format version {got}, expected {version}")
+        }
+        Ok(res)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            bail!("unexpected end of file")
+        }
+        let (res, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(res)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    pub fn usize(&mut self) -> Result<usize> {
+        Ok(usize::try_from(u64::from_le_bytes(self.take(8)?.try_into()?))?)
+    }
+
+    pub fn str(&mut self) -> Result<String> {
+        let len = self.usize()?;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    pub fn position(&mut self) -> Result<Position> {
+        self.nested_position(0)
+    }
+
+    fn nested_position(&mut self, depth: usize) -> Result<Position> {
+        if depth > MAX_NESTING_DEPTH {
+            bail!("position nested deeper than {MAX_NESTING_DEPTH} expansions")
+        }
+        let filename = self.str()?;
+        let line = self.usize()?;
+        let column = self.usize()?;
+        let expansion = match self.u8()? {
+            0 => None,
+            1 => Some(Box::new(self.nested_position(depth + 1)?)),
+            tag => bail!("invalid position tag {tag}"),
+        };
+        Ok(Position{filename, line, column, expansion})
+    }
+
+    pub fn expression(&mut self) -> Result<Expression> {
+        self.nested_expression(0)
+    }
+
+    fn nested_expression(&mut self, depth: usize) -> Result<Expression> {
+        if depth > MAX_NESTING_DEPTH {
+            bail!("expression nested deeper than {MAX_NESTING_DEPTH} levels")
+        }
+        Ok(match self.u8()? {
+            0 => Expression::Integer(self.i64()?),
+            1 => Expression::Symbol(self.str()?),
+            2 => Expression::Negate(Box::new(self.nested_expression(depth + 1)?)),
+            3 => {
+                let op = match self.u8()? {
+                    0 => BinaryOperator::Add,
+                    1 => BinaryOperator::Sub,
+                    2 => BinaryOperator::Mul,
+                    3 => BinaryOperator::Div,
+                    4 => BinaryOperator::Rem,
+                    tag => bail!("invalid operator tag {tag}"),
+                };
+                Expression::Binary(op, Box::new(self.nested_expression(depth + 1)?), Box::new(self.nested_expression(depth + 1)?))
+            },
+            tag => bail!("invalid expression tag {tag}"),
+        })
+    }
+
+    pub fn token(&mut self) -> Result<Token> {
+        let tag = self.u8()?;
+        let token = match tag {
+            0 => Token::Integer(self.i64()?, Position::default()),
+            1 => Token::Declaration(self.str()?, Position::default()),
+            2 => Token::Ident(self.str()?, Position::default()),
+            3 => Token::Directive(self.str()?, Position::default()),
+            4 => Token::String(self.str()?, Position::default()),
+            5 => Token::Constant(self.str()?, self.i64()?, Position::default()),
+            6 => Token::Expression(self.expression()?, Position::default()),
+            _ => bail!("invalid token tag {tag}"),
+        };
+        Ok(token.with_position(self.position()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::tokenize::tokenize;
+
+    #[test]
+    fn roundtrips_tokens() {
+        let mut tokens = tokenize("1 :a b %rep \"s\" (a+1)*-2", "test").unwrap();
+        tokens.push(Token::Constant("c".to_string(), -5, tokens[0].position().expanded(tokens[1].position())));
+        let mut writer = Writer::new(b"TEST", 1);
+        for token in &tokens {
+            writer.token(token);
+        }
+        let bytes = writer.into_bytes();
+
+        let mut reader = Reader::new(&bytes, b"TEST", 1).unwrap();
+        let got = tokens.iter().map(|_| reader.token().unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(got, tokens);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn error_on_too_deep_position() {
+        let mut writer = Writer::new(b"TEST", 1);
+        for _ in 0..100_000 {
+            writer.str("test");
+            writer.usize(1);
+            writer.usize(1);
+            writer.u8(1);
+        }
+        let bytes = writer.into_bytes();
+
+        let got = Reader::new(&bytes, b"TEST", 1).unwrap().position();
+
+        assert_eq!(got.unwrap_err().to_string(), "position nested deeper than 256 expansions");
+    }
+
+    #[test]
+    fn error_on_too_deep_expression() {
+        let mut bytes = Writer::new(b"TEST", 1).into_bytes();
+        bytes.extend([2; 100_000]);
+
+        let got = Reader::new(&bytes, b"TEST", 1).unwrap().expression();
+
+        assert_eq!(got.unwrap_err().to_string(), "expression nested deeper than 256 levels");
+    }
+
+    #[test]
+    fn error_on_wrong_header() {
+        let bytes = Writer::new(b"TEST", 2).into_bytes();
+
+        let got = [Reader::new(&bytes, b"TEST", 1).err(), Reader::new(&bytes, b"BEST", 2).err(), Reader::new(&bytes[..6], b"TEST", 2).err()];
+
+        assert_eq!(got.map(|x| x.unwrap().to_string()), [
+            "unsupported format version 2, expected 1",
+            "invalid file format",
+            "unexpected end of file",
+        ]);
+    }
+}
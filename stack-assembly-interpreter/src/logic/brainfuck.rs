@@ -0,0 +1,143 @@
+use anyhow::Result;
+
+use crate::models::command::Instruction;
+use crate::models::error::{CollectErrors as _, error_at};
+use crate::models::token::{Position, Token};
+use super::assembly::{self, TextFile};
+use super::tokenize::emit;
+
+/// Number of cells in tape after the code. Cells hold bytes, wrapping around on overflow.
+const TAPE_SIZE: usize = 30000;
+
+/// Generated labels contain `#`, so they can't clash with ones of the code.
+fn jump(out: &mut Vec<Token>, label: &str, id: usize, mnemonic: &str, pos: &Position) {
+    out.push(Token::Ident(format!("bf#{label}#{id}"), pos.clone()));
+    out.push(Token::Ident(mnemonic.to_string(), pos.clone()));
+}
+
+fn declaration(label: &str, id: usize, pos: &Position) -> Token {
+    Token::Declaration(format!("bf#{label}#{id}"), pos.clone())
+}
+
+/// Returns commands of Brainfuck program with their positions, other characters are comments.
+fn commands(file: &TextFile) -> Vec<(char, Position)> {
+    file.text.lines()
+             .enumerate()
+             .flat_map(|(line, text)| text.chars()
+                                          .enumerate()
+                                          .filter(|(_, c)| "+-<>.,[]".contains(*c))
+                                          .map(move |(column, c)| (c, Position{filename: file.name.clone(), line: line+1, column: column+1, expansion: None})))
+             .collect()
+}
+
+/// Compiles Brainfuck program. Tape pointer is kept on top of the stack and wraps around tape,
+/// runs of `+-<>` are folded, `,` stores 0 at end of input.
+pub fn compile(files: &[TextFile]) -> Result<Vec<Instruction>> {
+    let start = Position{filename: files.first().map(|x| x.name.clone()).unwrap_or_default(), line: 1, column: 1, expansion: None};
+    let mut res = vec![];
+    // NOTE: tape is zeroed from its end, leaving pointer at its start
+    emit(&mut res, &format!("(PROGRAM_SIZE+{TAPE_SIZE})"), &start)?;
+    res.push(declaration("clear", 0, &start));
+    emit(&mut res, "1 SUB DUP 0 SAVE DUP (PROGRAM_SIZE) CMP", &start)?;
+    jump(&mut res, "clear", 0, "JNE", &start);
+
+    let mut errors: Vec<Result<()>> = vec![];
+    let mut loops: Vec<(usize, Position)> = vec![];
+    let mut count = 0;
+    for file in files {
+        let commands = commands(file);
+        let mut i = 0;
+        while let Some((c, pos)) = commands.get(i) {
+            let run = commands[i..].iter()
+                                   .take_while(|(x, _)| x == c)
+                                   .count();
+            i += match c {
+                '+' | '-' | '<' | '>' => run,
+                _ => 1,
+            };
+            match c {
+                '+' => emit(&mut res, &format!("DUP DUP LOAD {run} ADD 255 BITAND SAVE"), pos)?,
+                '-' => emit(&mut res, &format!("DUP DUP LOAD {run} SUB 255 BITAND SAVE"), pos)?,
+                // NOTE: pointer wraps around tape, moving left is moving right by the rest of it
+                '>' | '<' => {
+                    let offset = match c {
+                        '>' => run % TAPE_SIZE,
+                        _ => TAPE_SIZE - run % TAPE_SIZE,
+                    };
+                    emit(&mut res, &format!("(PROGRAM_SIZE) SUB {offset} ADD {TAPE_SIZE} MOD (PROGRAM_SIZE) ADD"), pos)?;
+                },
+                '.' => emit(&mut res, "DUP LOAD OUT", pos)?,
+                ',' => {
+                    count += 1;
+                    emit(&mut res, "DUP IN DUP", pos)?;
+                    jump(&mut res, "input", count, "JGE", pos);
+                    emit(&mut res, "DROP 0", pos)?;
+                    res.push(declaration("input", count, pos));
+                    emit(&mut res, "SAVE", pos)?;
+                },
+                '[' => {
+                    count += 1;
+                    emit(&mut res, "DUP LOAD", pos)?;
+                    jump(&mut res, "end", count, "JEQ", pos);
+                    res.push(declaration("loop", count, pos));
+                    loops.push((count, pos.clone()));
+                },
+                _ => match loops.pop() {
+                    Some((id, _)) => {
+                        emit(&mut res, "DUP LOAD", pos)?;
+                        jump(&mut res, "loop", id, "JNE", pos);
+                        res.push(declaration("end", id, pos));
+                    },
+                    None => errors.push(Err(error_at!(pos, "] without matching ["))),
+                },
+            }
+        }
+    }
+    for (_, pos) in loops {
+        errors.push(Err(error_at!(pos, "unterminated [, expected ]")));
+    }
+    errors.into_iter().collect_errors::<()>()?;
+    emit(&mut res, "0 HALT", &start)?;
+    assembly::assemble_tokens(&res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::stdio::testing;
+
+    fn output(text: &str, input: &str) -> Result<String> {
+        let instructions = compile(&[TextFile{name: "test".to_string(), text: text.to_string()}])?;
+        Ok(testing::run(instructions, input)?.0)
+    }
+
+    #[test]
+    fn runs_hello_world() {
+        let text = "
+            ++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+        assert_eq!(output(text, "").unwrap(), "Hello World!\n");
+    }
+
+    #[test]
+    fn reads_input_until_end() {
+        // NOTE: prints input with each character incremented, end of input reads 0
+        assert_eq!(output(",[+.,]", "HAL").unwrap(), "IBM");
+    }
+
+    #[test]
+    fn wraps_cells_around_byte() {
+        assert_eq!(output("-.>+++++++++++++++++[<++++>-]<.", "").unwrap(), "\u{ff}\u{43}");
+    }
+
+    #[test]
+    fn wraps_pointer_around_tape() {
+        assert_eq!(output("<++++++++[>++++++++<-]>+.", "").unwrap(), "A");
+    }
+
+    #[test]
+    fn error_on_unbalanced_brackets() {
+        assert_eq!(output("+[\n[-]]]", "").unwrap_err().to_string(), "test:2:5: ] without matching [");
+        assert_eq!(output("[ comment\n [", "").unwrap_err().to_string(), "test:1:1: unterminated [, expected ]\ntest:2:2: unterminated [, expected ]");
+    }
+}
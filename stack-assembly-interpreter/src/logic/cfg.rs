@@ -0,0 +1,255 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::models::command::{Instruction, Opcode};
+use crate::models::lint::Warning;
+use crate::models::token::Token;
+use super::command::{get_command, is_command};
+use super::visibility::source_name;
+
+/// Straight-line run of instructions `start..end`, indices are relative to program start.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+    pub successors: Vec<usize>,
+}
+
+enum Transfer {
+    Jump(Option<usize>),
+    Branch(Option<usize>),
+    Call(Option<usize>),
+    Stop,
+    Next,
+}
+
+/// Returns instruction index of code address pushed right before instruction `i`.
+fn target(code: &[Instruction], i: usize) -> Option<usize> {
+    let opcode = code.get(i.checked_sub(1)?)?.opcode;
+    let target = usize::try_from(opcode.checked_sub(256)?).ok()?;
+    (target < code.len()).then_some(target)
+}
+
+fn transfer(code: &[Instruction], i: usize) -> Transfer {
+    let opcode = code[i].opcode;
+    let Some(cmd) = get_command(opcode) else {
+        return Transfer::Next
+    };
+    match cmd.mnemonics[0] {
+        "SETIP" => Transfer::Jump(target(code, i)),
+        "RET2" | "HALT" => Transfer::Stop,
+        "CALL" => Transfer::Call(target(code, i)),
+        mnemonic if mnemonic.starts_with('J') => Transfer::Branch(target(code, i)),
+        _ => Transfer::Next,
+    }
+}
+
+/// Splits code into blocks at `labels` and jump targets, edges follow jumps with known targets and calls,
+/// assuming that called routines return. Indirect jumps and returns have no successors.
+pub fn build(code: &[Instruction], labels: &[usize]) -> Vec<Block> {
+    let mut leaders: BTreeSet<usize> = labels.iter().copied().filter(|x| *x < code.len()).collect();
+    leaders.insert(0);
+    for i in 0..code.len() {
+        match transfer(code, i) {
+            Transfer::Next => continue,
+            Transfer::Jump(Some(target)) | Transfer::Branch(Some(target)) | Transfer::Call(Some(target)) => leaders.insert(target),
+            _ => false,
+        };
+        leaders.insert(i+1);
+    }
+    leaders.retain(|x| *x < code.len());
+
+    let starts: Vec<usize> = leaders.into_iter().collect();
+    let block_of: HashMap<usize, usize> = starts.iter().enumerate().map(|(i, start)| (*start, i)).collect();
+    starts.iter()
+          .enumerate()
+          .map(|(i, start)| {
+              let end = starts.get(i+1).copied().unwrap_or(code.len());
+              let next = (end < code.len()).then_some(end);
+              let successors = match transfer(code, end-1) {
+                  Transfer::Jump(target) => vec![target],
+                  Transfer::Stop => vec![],
+                  Transfer::Branch(target) | Transfer::Call(target) => vec![target, next],
+                  Transfer::Next => vec![next],
+              };
+              let successors = successors.into_iter().flatten().map(|x| block_of[&x]).collect();
+              Block{start: *start, end, successors}
+          })
+          .collect()
+}
+
+fn is_jump(opcode: Opcode) -> bool {
+    ["SETIP", "CALL"].iter().any(|x| is_command(opcode, x))
+        || get_command(opcode).is_some_and(|x| x.mnemonics[0].starts_with('J'))
+}
+
+/// Control flow of assembled program, see [`analyze`].
+pub struct Flow<'a> {
+    code: &'a [Instruction],
+    /// Declared labels with their declarations and instruction indices.
+    declarations: Vec<(&'a str, &'a Token, usize)>,
+    referenced: HashSet<&'a str>,
+    /// Labels referenced right before jump or call.
+    targets: HashSet<&'a str>,
+    blocks: Vec<Block>,
+    /// Whether each block can be reached from program start.
+    reachable: Vec<bool>,
+}
+
+/// Finds code reachable from program start.
+/// Label referenced other than right before jump or call is considered data if it points at a literal,
+/// otherwise its code may be reached through indirect jump.
+pub fn analyze<'a>(tokens: &'a [Token], code: &'a [Instruction], labels: &HashMap<&str, Opcode>) -> Flow<'a> {
+    let mut referenced = HashSet::new();
+    let mut targets = HashSet::new();
+    for (i, instruction) in code.iter().enumerate() {
+        let names = match &instruction.token {
+            Token::Ident(name, _) => vec![name],
+            Token::Expression(expression, _) => expression.symbols(),
+            _ => continue,
+        };
+        let jump = matches!(&instruction.token, Token::Ident(_, _)) && code.get(i+1).is_some_and(|x| is_jump(x.opcode));
+        for name in names {
+            referenced.insert(name.as_str());
+            if jump {
+                targets.insert(name.as_str());
+            }
+        }
+    }
+
+    let declarations: Vec<(&str, &Token, usize)> = tokens.iter()
+                                                         .filter_map(|x| match x {
+                                                             Token::Declaration(name, _) => Some((name.as_str(), x, (labels[name.as_str()] - 256) as usize)),
+                                                             _ => None,
+                                                         })
+                                                         .collect();
+    let blocks = build(code, &declarations.iter().map(|(_, _, i)| *i).collect::<Vec<_>>());
+    let mut flow = Flow{code, declarations, referenced, targets, reachable: vec![false; blocks.len()], blocks};
+
+    let mut roots = vec![0];
+    for (name, _, i) in &flow.declarations {
+        if flow.referenced.contains(name) && !flow.targets.contains(name) && !flow.is_data(name, *i) {
+            roots.extend(flow.blocks.iter().position(|x| x.start == *i));
+        }
+    }
+    while let Some(block) = roots.pop() {
+        if block < flow.blocks.len() && !flow.reachable[block] {
+            flow.reachable[block] = true;
+            roots.extend(&flow.blocks[block].successors);
+        }
+    }
+    flow
+}
+
+impl Flow<'_> {
+    fn is_data(&self, name: &str, i: usize) -> bool {
+        self.referenced.contains(name) && !self.targets.contains(name)
+            && self.code.get(i).is_some_and(|x| get_command(x.opcode).is_none())
+    }
+
+    /// Whether instruction at index `i` can be executed.
+    pub fn is_reachable(&self, i: usize) -> bool {
+        self.blocks.iter()
+                   .position(|x| (x.start..x.end).contains(&i))
+                   .is_some_and(|block| self.reachable[block])
+    }
+
+    /// Warns about unreachable code, labels which are never referenced and execution falling through into data.
+    pub fn warnings(&self) -> Vec<Warning> {
+        let mut res = vec![];
+        for (name, token, _) in &self.declarations {
+            if !self.referenced.contains(name) {
+                res.push(Warning{
+                    lint: "unused_label",
+                    position: token.position().clone(),
+                    message: format!("label is never referenced: {}", source_name(name)),
+                });
+            }
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            let data = self.declarations.iter().find(|(name, _, start)| *start == block.start && self.is_data(name, *start));
+            let falls_through = i > 0 && self.reachable[i-1] && self.blocks[i-1].successors.contains(&i) && self.blocks[i-1].end == block.start;
+            match data {
+                Some((name, token, _)) if falls_through => res.push(Warning{
+                    lint: "fall_through_into_data",
+                    position: token.position().clone(),
+                    message: format!("execution falls through into data label: {}", source_name(name)),
+                }),
+                Some(_) => {},
+                None if !self.reachable[i] => res.push(Warning{
+                    lint: "unreachable_code",
+                    position: self.code[block.start].token.position().clone(),
+                    message: "unreachable code".to_string(),
+                }),
+                None => {},
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::assembly::{self, Options, TextFile};
+
+    fn warnings_of(text: &str) -> Vec<String> {
+        let files = [TextFile{name: "test".to_owned(), text: text.to_owned()}];
+        let tokens = assembly::preprocess(&files[0], &Options::default()).unwrap();
+        let labels = crate::logic::labels::get_labels(&tokens).unwrap();
+        let code = assembly::assembly(&files, &Options::default()).unwrap();
+        analyze(&tokens, &code, &labels).warnings()
+                                        .iter()
+                                        .map(ToString::to_string)
+                                        .collect()
+    }
+
+    #[test]
+    fn builds_blocks_with_edges() {
+        let code = assembly::assembly(&[TextFile{name: "test".to_owned(), text: String::from(":A 1 B JNE A CALL :B 0 HALT")}], &Options::default()).unwrap();
+
+        let got = build(&code, &[0, 5]);
+
+        assert_eq!(got, vec![
+            Block{start: 0, end: 3, successors: vec![2, 1]},
+            Block{start: 3, end: 5, successors: vec![0, 2]},
+            Block{start: 5, end: 7, successors: vec![]},
+        ]);
+    }
+
+    #[test]
+    fn accepts_main_asm() {
+        let text = std::fs::read_to_string("main.asm").unwrap();
+
+        let got = warnings_of(&text);
+
+        assert_eq!(got, Vec::<String>::new());
+    }
+
+    #[test]
+    fn warns_about_unreachable_code_and_unused_labels() {
+        let got = warnings_of("Main JMP 1 HALT\n:Main 0 HALT\n:Unused 2 HALT");
+
+        assert_eq!(got, vec![
+            "test:3:1: warning[unused_label]: label is never referenced: Unused",
+            "test:1:10: warning[unreachable_code]: unreachable code",
+            "test:3:9: warning[unreachable_code]: unreachable code",
+        ]);
+    }
+
+    #[test]
+    fn warns_about_fall_through_into_data() {
+        let got = warnings_of("String LOAD OUT\n:String 72 0 HALT\n:Table 1 2\n:Code Table Code JMP");
+
+        assert_eq!(got, vec![
+            "test:2:1: warning[fall_through_into_data]: execution falls through into data label: String",
+            "test:4:7: warning[unreachable_code]: unreachable code",
+        ]);
+    }
+
+    #[test]
+    fn accepts_extreme_operands() {
+        let got = warnings_of("Min EQU -0x8000_0000_0000_0000\nMin DROP Min JMP 0 HALT");
+
+        assert_eq!(got, vec!["test:2:18: warning[unreachable_code]: unreachable code"]);
+    }
+}
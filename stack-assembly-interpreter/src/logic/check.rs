@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::models::command::Instruction;
+use crate::models::error::{CollectErrors as _, error_at};
+use super::command::{get_command, is_command};
+
+/// Stack usage of routine relative to depth right after `CALL` popped target address.
+#[derive(Clone, Copy)]
+struct Summary {
+    /// Lowest depth reached, negative if routine consumes caller's values.
+    min: i64,
+    /// Depth after return, `None` if routine never returns.
+    exit: Option<i64>,
+}
+
+struct Checker<'a> {
+    code: &'a [Instruction],
+    /// Summaries of routines, `None` while routine is being checked.
+    summaries: HashMap<usize, Option<Summary>>,
+    errors: Vec<Result<()>>,
+}
+
+impl Checker<'_> {
+    /// Returns address of code pushed right before jump instruction at `i`.
+    fn target(&self, i: usize) -> Option<usize> {
+        let opcode = self.code.get(i.checked_sub(1)?)?.opcode;
+        let target = usize::try_from(opcode.checked_sub(256)?).ok()?;
+        (target < self.code.len()).then_some(target)
+    }
+
+    fn error(&mut self, i: usize, message: String) {
+        self.errors.push(Err(error_at!(self.code[i].token.position(), "{message}")));
+    }
+
+    fn summary(&mut self, entry: usize) -> Option<Summary> {
+        if let Some(summary) = self.summaries.get(&entry) {
+            return *summary
+        }
+        self.summaries.insert(entry, None);
+        // NOTE: return address pushed by CALL
+        let summary = self.walk(entry, 1, false);
+        self.summaries.insert(entry, summary);
+        summary
+    }
+
+    /// Walks code reachable from `entry` with `depth` values on stack, following jumps but not calls.
+    /// Underflow below initial stack is reported only if `strict`, otherwise it's reflected in summary.
+    /// After `SETSP` or call of routine with unknown summary, walk goes on with unknown depth.
+    fn walk(&mut self, entry: usize, depth: i64, strict: bool) -> Option<Summary> {
+        // NOTE: instruction is walked again if it was reached with unknown depth first
+        let mut depths: HashMap<usize, Option<i64>> = HashMap::new();
+        let mut queue = vec![(entry, Some(depth))];
+        let mut min = depth.min(0);
+        let mut exit = None;
+        let mut known = true;
+        while let Some((i, depth)) = queue.pop() {
+            if i >= self.code.len() {
+                continue
+            }
+            match (depths.get(&i), depth) {
+                (Some(Some(expected)), Some(depth)) if *expected != depth => {
+                    self.error(i, format!("stack depth mismatch at merge point: {expected} and {depth}"));
+                    continue
+                },
+                (Some(Some(_)), _) | (Some(None), None) => continue,
+                _ => depths.insert(i, depth),
+            };
+
+            let opcode = self.code[i].opcode;
+            let Some(cmd) = get_command(opcode) else {
+                if opcode < 0 {
+                    self.error(i, format!("invalid opcode {opcode}"));
+                    continue
+                }
+                queue.push((i+1, depth.map(|x| x+1)));
+                continue
+            };
+            let (pops, pushes) = cmd.arity();
+            let after = depth.map(|x| x - pops as i64);
+            if after.is_some_and(|x| x < 0) && strict {
+                self.error(i, format!("stack underflow: {} needs {pops} values, stack has {}", cmd.mnemonics[0], depth.unwrap()));
+                continue
+            }
+            if let Some(after) = after {
+                min = min.min(after);
+            }
+            let depth = after.map(|x| x + pushes as i64);
+
+            if is_command(opcode, "HALT") {
+                continue
+            }
+            if is_command(opcode, "SETSP") {
+                known = false;
+                queue.push((i+1, None));
+                continue
+            }
+            if is_command(opcode, "SETIP") || is_command(opcode, "RET2") {
+                match (self.target(i), depth) {
+                    (Some(target), _) if is_command(opcode, "SETIP") => queue.push((target, depth)),
+                    (_, None) => known = false,
+                    (_, Some(depth)) => match exit {
+                        Some(expected) if expected != depth => {
+                            self.error(i, format!("routine returns with different stack depths: {expected} and {depth}"));
+                        },
+                        _ => exit = Some(depth),
+                    },
+                }
+                continue
+            }
+            if is_command(opcode, "CALL") {
+                let Some(summary) = self.target(i).and_then(|target| self.summary(target)) else {
+                    known = false;
+                    queue.push((i+1, None));
+                    continue
+                };
+                if let Some(after) = after.filter(|x| x + summary.min < 0) {
+                    if strict {
+                        self.error(i, format!("stack underflow in called routine: it needs {} values, stack has {after}", -summary.min));
+                        continue
+                    }
+                    min = min.min(after + summary.min);
+                }
+                if let Some(exit) = summary.exit {
+                    queue.push((i+1, after.map(|x| x + exit)));
+                }
+                continue
+            }
+            if cmd.mnemonics[0].starts_with('J') {
+                if let Some(target) = self.target(i) {
+                    queue.push((target, depth));
+                }
+            }
+            queue.push((i+1, depth));
+        }
+        known.then_some(Summary{min, exit})
+    }
+}
+
+/// Checks stack depth along all paths from program start, reporting underflows,
+/// depth mismatches where paths merge and routines returning with different depths.
+/// Jumps and calls are followed only if target address is pushed right before them.
+pub fn check(code: &[Instruction]) -> Result<()> {
+    let mut checker = Checker{code, summaries: HashMap::new(), errors: vec![]};
+    checker.walk(0, 0, true);
+    checker.errors.into_iter().collect_errors::<()>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::assembly::{self, Options, TextFile};
+
+    fn check_text(text: &str) -> Result<()> {
+        check(&assembly::assembly(&[TextFile{name: "test".to_owned(), text: text.to_owned()}], &Options::default())?)
+    }
+
+    #[test]
+    fn accepts_main_asm() {
+        let text = std::fs::read_to_string("main.asm").unwrap();
+
+        let got = check(&assembly::assembly(&[TextFile{name: "main.asm".to_owned(), text}], &Options::default()).unwrap());
+
+        assert!(got.is_ok(), "{got:?}");
+    }
+
+    #[test]
+    fn error_on_underflow() {
+        let got = check_text("1 ADD 0 HALT");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:3: stack underflow: ADD needs 2 values, stack has 1");
+    }
+
+    #[test]
+    fn error_on_depth_mismatch_at_merge_point() {
+        let got = check_text(":Loop 1 Loop JMP");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:7: stack depth mismatch at merge point: 0 and 1");
+    }
+
+    #[test]
+    fn error_on_underflow_in_called_routine() {
+        let got = check_text("1 Sum CALL HALT\n:Sum SETFP ADD GETFP RET");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:7: stack underflow in called routine: it needs 2 values, stack has 1");
+    }
+
+    #[test]
+    fn error_on_different_return_depths() {
+        let got = check_text("F CALL 0 HALT\n:F SETFP 1 .a JEQ 5 GETFP RET :.a GETFP RET");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:2:41: routine returns with different stack depths: 1 and 0");
+    }
+
+    #[test]
+    fn checks_code_after_call_of_recursive_routine() {
+        let got = check_text("R CALL F CALL HALT\n:R SETFP R CALL GETFP RET\n:F SETFP 1 .a JEQ 5 GETFP RET :.a GETFP RET");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:3:41: routine returns with different stack depths: 1 and 0");
+    }
+
+    #[test]
+    fn error_on_out_of_range_opcode() {
+        let got = check_text("-0x8000_0000_0000_0000 JMP");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:1: invalid opcode -9223372036854775808");
+    }
+}
@@ -3,59 +3,64 @@ use anyhow::{anyhow, Result};
 use crate::models::{command::{Command, CommandHandler, InputOutput, Opcode, ReturnCode}, vm::VM};
 
 pub const COMMANDS: [Option<Command>; 44] = [
-    Some(Command{mnemonics: &["ADD"], handler: &AddHandler{}}),
-    Some(Command{mnemonics: &["SUB"], handler: &SubHandler{}}),
-    Some(Command{mnemonics: &["BITAND"], handler: &BitwiseAndHandler{}}),
-    Some(Command{mnemonics: &["BITOR"], handler: &BitwiseOrHandler{}}),
-    Some(Command{mnemonics: &["BITXOR"], handler: &BitwiseXorHandler{}}),
-    Some(Command{mnemonics: &["LSHIFT"], handler: &LeftShiftHandler{}}),
-    Some(Command{mnemonics: &["RSHIFT"], handler: &RightShiftHandler{}}),
-    Some(Command{mnemonics: &["CMP"], handler: &CmpHandler{}}),
-    Some(Command{mnemonics: &["GETIP"], handler: &GetIPHandler{}}),
-    Some(Command{mnemonics: &["GETSP"], handler: &GetSPHandler{}}),
-    Some(Command{mnemonics: &["GETFP"], handler: &GetFPHandler{}}),
-    Some(Command{mnemonics: &["GETRV"], handler: &GetRVHandler{}}),
-    Some(Command{mnemonics: &["SETIP", "JMP", "RET"], handler: &SetIPHandler{}}),
-    Some(Command{mnemonics: &["SETSP"], handler: &SetSPHandler{}}),
-    Some(Command{mnemonics: &["SETFP"], handler: &SetFPHandler{}}),
-    Some(Command{mnemonics: &["SETRV"], handler: &SetRVHandler{}}),
-    Some(Command{mnemonics: &["RET2"], handler: &Ret2Handler{}}),
-    Some(Command{mnemonics: &["JGE"], handler: &JgeHandler{}}),
-    Some(Command{mnemonics: &["JNE"], handler: &JneHandler{}}),
-    Some(Command{mnemonics: &["JGT"], handler: &JgtHandler{}}),
-    Some(Command{mnemonics: &["JLE"], handler: &JleHandler{}}),
-    Some(Command{mnemonics: &["JEQ"], handler: &JeqHandler{}}),
-    Some(Command{mnemonics: &["JLT"], handler: &JltHandler{}}),
-    Some(Command{mnemonics: &["DROP2"], handler: &Drop2Handler{}}),
-    Some(Command{mnemonics: &["DUP"], handler: &DupHandler{}}),
-    Some(Command{mnemonics: &["DROP"], handler: &DropHandler{}}),
-    Some(Command{mnemonics: &["SWAP"], handler: &SwapHandler{}}),
-    Some(Command{mnemonics: &["ROT"], handler: &RotHandler{}}),
-    Some(Command{mnemonics: &["OVER"], handler: &OverHandler{}}),
-    Some(Command{mnemonics: &["SDROP"], handler: &SdropHandler{}}),
-    Some(Command{mnemonics: &["CALL"], handler: &CallHandler{}}),
+    Some(Command{mnemonics: &["ADD"], effect: "x y -- x+y", handler: &AddHandler{}}),
+    Some(Command{mnemonics: &["SUB"], effect: "x y -- x-y", handler: &SubHandler{}}),
+    Some(Command{mnemonics: &["BITAND"], effect: "x y -- x&y", handler: &BitwiseAndHandler{}}),
+    Some(Command{mnemonics: &["BITOR"], effect: "x y -- x|y", handler: &BitwiseOrHandler{}}),
+    Some(Command{mnemonics: &["BITXOR"], effect: "x y -- x^y", handler: &BitwiseXorHandler{}}),
+    Some(Command{mnemonics: &["LSHIFT"], effect: "x y -- x<<y", handler: &LeftShiftHandler{}}),
+    Some(Command{mnemonics: &["RSHIFT"], effect: "x y -- x>>y", handler: &RightShiftHandler{}}),
+    Some(Command{mnemonics: &["CMP"], effect: "x y -- sign(x-y)", handler: &CmpHandler{}}),
+    Some(Command{mnemonics: &["GETIP"], effect: "-- ip", handler: &GetIPHandler{}}),
+    Some(Command{mnemonics: &["GETSP"], effect: "-- sp", handler: &GetSPHandler{}}),
+    Some(Command{mnemonics: &["GETFP"], effect: "-- fp", handler: &GetFPHandler{}}),
+    Some(Command{mnemonics: &["GETRV"], effect: "-- rv", handler: &GetRVHandler{}}),
+    Some(Command{mnemonics: &["SETIP", "JMP", "RET"], effect: "addr --", handler: &SetIPHandler{}}),
+    Some(Command{mnemonics: &["SETSP"], effect: "sp --", handler: &SetSPHandler{}}),
+    Some(Command{mnemonics: &["SETFP"], effect: "fp --", handler: &SetFPHandler{}}),
+    Some(Command{mnemonics: &["SETRV"], effect: "rv --", handler: &SetRVHandler{}}),
+    Some(Command{mnemonics: &["RET2"], effect: "x addr --", handler: &Ret2Handler{}}),
+    Some(Command{mnemonics: &["JGE"], effect: "x addr --", handler: &JgeHandler{}}),
+    Some(Command{mnemonics: &["JNE"], effect: "x addr --", handler: &JneHandler{}}),
+    Some(Command{mnemonics: &["JGT"], effect: "x addr --", handler: &JgtHandler{}}),
+    Some(Command{mnemonics: &["JLE"], effect: "x addr --", handler: &JleHandler{}}),
+    Some(Command{mnemonics: &["JEQ"], effect: "x addr --", handler: &JeqHandler{}}),
+    Some(Command{mnemonics: &["JLT"], effect: "x addr --", handler: &JltHandler{}}),
+    Some(Command{mnemonics: &["DROP2"], effect: "x y --", handler: &Drop2Handler{}}),
+    Some(Command{mnemonics: &["DUP"], effect: "x -- x x", handler: &DupHandler{}}),
+    Some(Command{mnemonics: &["DROP"], effect: "x --", handler: &DropHandler{}}),
+    Some(Command{mnemonics: &["SWAP"], effect: "x y -- y x", handler: &SwapHandler{}}),
+    Some(Command{mnemonics: &["ROT"], effect: "x y z -- y z x", handler: &RotHandler{}}),
+    Some(Command{mnemonics: &["OVER"], effect: "x y -- x y x", handler: &OverHandler{}}),
+    Some(Command{mnemonics: &["SDROP"], effect: "x y -- y", handler: &SdropHandler{}}),
+    Some(Command{mnemonics: &["CALL"], effect: "addr -- ret", handler: &CallHandler{}}),
     None,
-    Some(Command{mnemonics: &["NEG"], handler: &NegHandler{}}),
-    Some(Command{mnemonics: &["BITNOT"], handler: &BitwiseNotHandler{}}),
-    Some(Command{mnemonics: &["LOAD"], handler: &LoadHandler{}}),
-    Some(Command{mnemonics: &["SAVE"], handler: &SaveHandler{}}),
-    Some(Command{mnemonics: &["HALT"], handler: &HaltHandler{}}),
+    Some(Command{mnemonics: &["NEG"], effect: "x -- -x", handler: &NegHandler{}}),
+    Some(Command{mnemonics: &["BITNOT"], effect: "x -- !x", handler: &BitwiseNotHandler{}}),
+    Some(Command{mnemonics: &["LOAD"], effect: "addr -- value", handler: &LoadHandler{}}),
+    Some(Command{mnemonics: &["SAVE"], effect: "addr value --", handler: &SaveHandler{}}),
+    Some(Command{mnemonics: &["HALT"], effect: "rc --", handler: &HaltHandler{}}),
     None,
     None,
-    Some(Command{mnemonics: &["MUL"], handler: &MulHandler{}}),
-    Some(Command{mnemonics: &["DIV"], handler: &DivHandler{}}),
-    Some(Command{mnemonics: &["MOD"], handler: &ModHandler{}}),
-    Some(Command{mnemonics: &["IN"], handler: &InHandler{}}),
-    Some(Command{mnemonics: &["OUT"], handler: &OutHandler{}}),
+    Some(Command{mnemonics: &["MUL"], effect: "x y -- x*y", handler: &MulHandler{}}),
+    Some(Command{mnemonics: &["DIV"], effect: "x y -- x/y", handler: &DivHandler{}}),
+    Some(Command{mnemonics: &["MOD"], effect: "x y -- x%y", handler: &ModHandler{}}),
+    Some(Command{mnemonics: &["IN"], effect: "-- c", handler: &InHandler{}}),
+    Some(Command{mnemonics: &["OUT"], effect: "c --", handler: &OutHandler{}}),
 ];
 
+pub fn get_command(opcode: Opcode) -> Option<&'static Command<'static>> {
+    let index = usize::try_from(opcode.checked_neg()?.checked_sub(1)?).ok()?;
+    COMMANDS.get(index)?.as_ref()
+}
+
+pub fn is_command(opcode: Opcode, mnemonic: &str) -> bool {
+    get_command(opcode).is_some_and(|x| x.mnemonics.contains(&mnemonic))
+}
+
 pub fn get_handler(opcode: Opcode) -> Result<&'static dyn CommandHandler> {
-    let index = -opcode as usize - 1;
-    COMMANDS.get(index)
-            .map(Option::as_ref)
-            .flatten()
-            .ok_or_else(|| anyhow!("no handler for opcode {opcode}"))
-            .map(|x| x.handler)
+    get_command(opcode).ok_or_else(|| anyhow!("no handler for opcode {opcode}"))
+                       .map(|x| x.handler)
 }
 
 macro_rules! handler {
@@ -310,7 +315,7 @@ mod tests {
                 opcode: -1,
                 token: Token::Ident(
                     "ADD".to_string(),
-                    Position{filename: "test".to_string(), line: 1, column: 3}
+                    Position{filename: "test".to_string(), line: 1, column: 3, expansion: None}
                 ),
             }
         ]);
@@ -321,4 +326,12 @@ mod tests {
 
         assert_eq!(vm.read_stack(0).unwrap(), 5)
     }
+
+    #[test]
+    fn no_command_for_out_of_range_opcodes() {
+        assert!(get_command(i64::MIN).is_none());
+        assert!(get_command(i64::MAX).is_none());
+        assert!(get_command(0).is_none());
+        assert_eq!(get_command(-1).unwrap().mnemonics, ["ADD"]);
+    }
 }
@@ -0,0 +1,195 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+
+use crate::models::command::Opcode;
+use crate::models::error::{bail_at, error_at};
+use crate::models::token::{Position, Token};
+use super::{expression, labels};
+
+struct Branch {
+    pos: Position,
+    /// Whether tokens of current branch are kept.
+    active: bool,
+    /// Whether some branch of this `%if` was already taken.
+    taken: bool,
+    in_else: bool,
+}
+
+/// Returns symbols declared in `preceding` tokens, labels are placed from `base` address.
+/// `%global`/`%extern` directives and names listed after them take no space.
+fn preceding_symbols(preceding: &[Token], base: Option<Opcode>) -> (HashMap<&str, Opcode>, HashSet<&str>) {
+    let mut symbols = labels::get_default_labels();
+    let mut declared = HashSet::new();
+    let mut current = base;
+    let mut directive: Option<&Position> = None;
+    for token in preceding {
+        match token {
+            Token::Directive(name, pos) if name == "global" || name == "extern" => directive = Some(pos),
+            Token::Ident(_, pos) if directive.is_some_and(|x| x.same_line(pos)) => {},
+            Token::Declaration(name, _) => {
+                declared.insert(name.as_str());
+                if let Some(address) = current {
+                    symbols.insert(name, address);
+                }
+            },
+            Token::Constant(name, value, _) => {
+                symbols.insert(name, *value);
+            },
+            _ => {
+                directive = None;
+                current = current.map(|x| x + 1);
+            },
+        }
+    }
+    (symbols, declared)
+}
+
+/// Evaluates condition of `%ifdef` or `%if` against `defines` and symbols declared in `preceding` tokens.
+/// Labels have their final addresses if `base` address of file is known, otherwise `%if` can't use them.
+fn evaluate(directive: &str, condition: Option<&Token>, preceding: &[Token], defines: &[(String, i64)], base: Option<Opcode>, pos: &Position) -> Result<bool> {
+    // NOTE: duplicate declarations are reported once labels of whole program are resolved
+    let (mut symbols, declared) = preceding_symbols(preceding, base);
+    for (name, value) in defines {
+        symbols.insert(name, *value);
+    }
+    let unknown = |name: &String| !symbols.contains_key(name.as_str()) && declared.contains(name.as_str());
+    match (directive, condition) {
+        ("ifdef", Some(Token::Ident(name, _))) => Ok(symbols.contains_key(name.as_str()) || declared.contains(name.as_str())),
+        ("ifdef", _) => bail_at!(pos, "%ifdef expects symbol name"),
+        (_, Some(Token::Integer(value, _))) => Ok(*value != 0),
+        (_, Some(Token::Ident(name, pos))) if unknown(name) => bail_at!(pos, "label address isn't known in separately assembled file: {name}"),
+        (_, Some(Token::Ident(name, pos))) => symbols.get(name.as_str())
+                                                     .map(|value| *value != 0)
+                                                     .ok_or_else(|| error_at!(pos, "undefined symbol in condition: \"{name}\"")),
+        (_, Some(Token::Expression(condition, pos))) => {
+            if let Some(name) = condition.symbols().into_iter().find(|name| unknown(name)) {
+                bail_at!(pos, "label address isn't known in separately assembled file: {name}")
+            }
+            expression::evaluate(condition, &symbols).map(|value| value != 0)
+                                                     .map_err(|err| error_at!(pos, "{err}"))
+        },
+        _ => bail_at!(pos, "%if expects integer, symbol or expression"),
+    }
+}
+
+/// Keeps tokens of taken `%ifdef NAME` / `%if expr` ... `%else` ... `%endif` branches.
+/// Conditions see `defines`, constants and labels declared earlier in the same file.
+/// `base` is address of first instruction of file, labels can't be used in `%if` when it isn't known.
+pub fn resolve(tokens: Vec<Token>, defines: &[(String, i64)], base: Option<Opcode>) -> Result<Vec<Token>> {
+    let mut res = vec![];
+    let mut branches: Vec<Branch> = vec![];
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        let active = branches.last().is_none_or(|x| x.active);
+        match token {
+            Token::Directive(directive, pos) if directive == "ifdef" || directive == "if" => {
+                let condition = tokens.next();
+                let active = active && evaluate(&directive, condition.as_ref(), &res, defines, base, &pos)?;
+                branches.push(Branch{pos, active, taken: active, in_else: false});
+            },
+            Token::Directive(directive, pos) if directive == "else" => {
+                let enclosing = branches.len() < 2 || branches[branches.len()-2].active;
+                let branch = branches.last_mut().ok_or_else(|| error_at!(pos, "%else without matching %if"))?;
+                if branch.in_else {
+                    bail_at!(pos, "%else after %else of %if at {}", branch.pos)
+                }
+                branch.active = enclosing && !branch.taken;
+                branch.in_else = true;
+            },
+            Token::Directive(directive, pos) if directive == "endif" => if branches.pop().is_none() {
+                bail_at!(pos, "%endif without matching %if")
+            },
+            _ => if active {
+                res.push(token)
+            },
+        }
+    }
+    if let Some(branch) = branches.last() {
+        bail_at!(branch.pos, "unterminated %if, expected %endif")
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::tokenize::tokenize;
+
+    fn resolved_at(text: &str, defines: &[(&str, i64)], base: Option<Opcode>) -> Result<String> {
+        let tokens = labels::resolve_local_labels(labels::fold_constants(tokenize(text, "test")?)?)?;
+        let defines = defines.iter().map(|(name, value)| (name.to_string(), *value)).collect::<Vec<_>>();
+        Ok(resolve(tokens, &defines, base)?.into_iter()
+                                     .map(|x| match x {
+                                         Token::Declaration(i, _) => format!(":{i}"),
+                                         Token::Ident(i, _) => i,
+                                         Token::Integer(i, _) => i.to_string(),
+                                         Token::Constant(i, value, _) => format!("{i}={value}"),
+                                         Token::Directive(i, _) => format!("%{i}"),
+                                         _ => panic!("unexpected token {x:?}"),
+                                     })
+                                     .collect::<Vec<_>>()
+                                     .join(" "))
+    }
+
+    fn resolved(text: &str, defines: &[(&str, i64)]) -> Result<String> {
+        resolved_at(text, defines, Some(256))
+    }
+
+    #[test]
+    fn keeps_branch_of_defined_symbol() {
+        let text = "%ifdef DEBUG 1 OUT %else 2 %endif HALT";
+
+        assert_eq!(resolved(text, &[("DEBUG", 1)]).unwrap(), "1 OUT HALT");
+        assert_eq!(resolved(text, &[]).unwrap(), "2 HALT");
+    }
+
+    #[test]
+    fn evaluates_conditions_with_constants_and_labels() {
+        let text = "
+            Level EQU 2
+            :Start
+            %if (Level-2) 1 %else 2 %endif
+            %ifdef Start %if Level 4 %else 5 %endif %else 6 %endif
+            %ifdef End 7 %endif
+            :End";
+
+        assert_eq!(resolved(text, &[]).unwrap(), "Level=2 :Start 2 4 :End");
+        assert_eq!(resolved(text, &[("Level", 0)]).unwrap(), "Level=2 :Start 1 5 :End");
+    }
+
+    #[test]
+    fn evaluates_labels_at_their_addresses() {
+        let text = "%global Start :Start 1 2 :Here :.loop %if (Here-258) 3 %else 4 %endif %if (.loop-Here) 5 %else 6 %endif";
+
+        assert_eq!(resolved_at(text, &[], Some(256)).unwrap(), "%global Start :Start 1 2 :Here :Here.loop 4 6");
+        assert_eq!(resolved_at(text, &[], Some(300)).unwrap(), "%global Start :Start 1 2 :Here :Here.loop 3 6");
+    }
+
+    #[test]
+    fn ignores_local_labels_declared_in_several_scopes() {
+        let text = ":A :.loop .loop JMP :B :.loop .loop JMP %ifdef DEBUG 1 %endif";
+
+        assert_eq!(resolved(text, &[("DEBUG", 1)]).unwrap(), ":A :A.loop A.loop JMP :B :B.loop B.loop JMP 1");
+    }
+
+    #[test]
+    fn skips_nested_conditions_of_inactive_branch() {
+        let got = resolved("%if 0 %if Undefined 1 %else 2 %endif %else 3 %endif", &[]);
+
+        assert_eq!(got.unwrap(), "3");
+    }
+
+    #[test]
+    fn error_on_unbalanced_directives() {
+        assert_eq!(resolved("%if 1 1", &[]).unwrap_err().to_string(), "test:1:1: unterminated %if, expected %endif");
+        assert_eq!(resolved("1 %endif", &[]).unwrap_err().to_string(), "test:1:3: %endif without matching %if");
+        assert_eq!(resolved("%else", &[]).unwrap_err().to_string(), "test:1:1: %else without matching %if");
+        assert_eq!(resolved("%ifdef A %else %else %endif", &[]).unwrap_err().to_string(),
+                   "test:1:16: %else after %else of %if at test:1:1");
+        assert_eq!(resolved("%if Missing %endif", &[]).unwrap_err().to_string(),
+                   "test:1:5: undefined symbol in condition: \"Missing\"");
+        assert_eq!(resolved_at(":Start %if (Start-256) 1 %endif", &[], None).unwrap_err().to_string(),
+                   "test:1:12: label address isn't known in separately assembled file: Start");
+    }
+}
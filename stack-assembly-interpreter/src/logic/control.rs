@@ -0,0 +1,167 @@
+use anyhow::Result;
+
+use crate::models::error::{SourceError, error_at, bail_at};
+use crate::models::token::{Position, Token};
+
+enum Construct {
+    If{id: usize, pos: Position, has_else: bool},
+    Begin{id: usize, pos: Position},
+    While{id: usize, pos: Position},
+}
+
+impl Construct {
+    fn word(&self) -> (&'static str, &Position) {
+        match self {
+            Construct::If{pos, ..} => ("IF", pos),
+            Construct::Begin{pos, ..} => ("BEGIN", pos),
+            Construct::While{pos, ..} => ("WHILE", pos),
+        }
+    }
+}
+
+/// Names of generated labels contain `#`, so they can't clash with declared ones.
+fn label(word: &str, id: usize) -> String {
+    format!("{word}#{id}")
+}
+
+fn jump(word: &str, id: usize, mnemonic: &str, pos: &Position) -> [Token; 2] {
+    [Token::Ident(label(word, id), pos.clone()), Token::Ident(mnemonic.to_string(), pos.clone())]
+}
+
+fn declaration(word: &str, id: usize, pos: &Position) -> Token {
+    Token::Declaration(label(word, id), pos.clone())
+}
+
+/// Lowers Forth-style `IF ... ELSE ... THEN`, `BEGIN ... UNTIL` and `BEGIN ... WHILE ... REPEAT`
+/// into generated labels and jumps. `IF`, `UNTIL` and `WHILE` pop a flag, zero is false.
+pub fn lower(tokens: Vec<Token>) -> Result<Vec<Token>> {
+    let mut res = vec![];
+    let mut constructs: Vec<Construct> = vec![];
+    let mut count = 0;
+    for token in tokens {
+        let Token::Ident(word, pos) = &token else {
+            res.push(token);
+            continue
+        };
+        match (word.as_str(), constructs.pop()) {
+            ("IF", top) => {
+                count += 1;
+                res.extend(jump("ELSE", count, "JEQ", pos));
+                constructs.extend(top);
+                constructs.push(Construct::If{id: count, pos: pos.clone(), has_else: false});
+            },
+            ("ELSE", Some(Construct::If{id, pos: if_pos, has_else: false})) => {
+                res.extend(jump("THEN", id, "JMP", pos));
+                res.push(declaration("ELSE", id, pos));
+                constructs.push(Construct::If{id, pos: if_pos, has_else: true});
+            },
+            ("THEN", Some(Construct::If{id, has_else, ..})) =>
+                res.push(declaration(if has_else { "THEN" } else { "ELSE" }, id, pos)),
+            ("BEGIN", top) => {
+                count += 1;
+                res.push(declaration("BEGIN", count, pos));
+                constructs.extend(top);
+                constructs.push(Construct::Begin{id: count, pos: pos.clone()});
+            },
+            ("UNTIL", Some(Construct::Begin{id, ..})) => res.extend(jump("BEGIN", id, "JEQ", pos)),
+            ("WHILE", Some(Construct::Begin{id, ..})) => {
+                res.extend(jump("REPEAT", id, "JEQ", pos));
+                constructs.push(Construct::While{id, pos: pos.clone()});
+            },
+            ("REPEAT", Some(Construct::While{id, ..})) => {
+                res.extend(jump("BEGIN", id, "JMP", pos));
+                res.push(declaration("REPEAT", id, pos));
+            },
+            ("ELSE" | "THEN" | "UNTIL" | "WHILE" | "REPEAT", Some(top)) => {
+                let (opening, opening_pos) = top.word();
+                return Err(SourceError::new(pos.clone(), format!("{word} doesn't match {opening}"))
+                               .with_label(opening_pos.clone(), &format!("{opening} opened here"))
+                               .into())
+            },
+            ("ELSE" | "THEN" | "UNTIL" | "WHILE" | "REPEAT", None) => {
+                let opening = match word.as_str() {
+                    "ELSE" | "THEN" => "IF",
+                    "REPEAT" => "WHILE",
+                    _ => "BEGIN",
+                };
+                bail_at!(pos, "{word} without matching {opening}")
+            },
+            (_, top) => {
+                constructs.extend(top);
+                res.push(token);
+            },
+        }
+    }
+    match constructs.pop() {
+        Some(Construct::If{pos, ..}) => Err(error_at!(pos, "unterminated IF, expected THEN")),
+        Some(Construct::Begin{pos, ..}) => Err(error_at!(pos, "unterminated BEGIN, expected UNTIL or WHILE")),
+        Some(Construct::While{pos, ..}) => Err(error_at!(pos, "unterminated WHILE, expected REPEAT")),
+        None => Ok(res),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::assembly::{self, Options, TextFile};
+    use crate::logic::stdio::Stdio;
+    use crate::logic::tokenize::tokenize;
+    use crate::logic::vm::Executor;
+    use crate::models::vm::VM;
+
+    fn lowered(text: &str) -> Result<String> {
+        Ok(lower(tokenize(text, "test")?)?.into_iter()
+                                          .map(|x| match x {
+                                              Token::Declaration(i, _) => format!(":{i}"),
+                                              Token::Ident(i, _) => i,
+                                              Token::Integer(i, _) => i.to_string(),
+                                              _ => panic!("unexpected token {x:?}"),
+                                          })
+                                          .collect::<Vec<_>>()
+                                          .join(" "))
+    }
+
+    fn run(text: &str) -> i64 {
+        let files = &[TextFile{name: "test".to_string(), text: text.to_string()}];
+        let instructions = assembly::assembly(files, &Options::default()).unwrap();
+        Executor{io: &mut Stdio::new()}.execute(VM::new(instructions)).unwrap()
+    }
+
+    #[test]
+    fn lowers_to_generated_labels_and_jumps() {
+        let got = lowered("1 IF 2 ELSE BEGIN 0 UNTIL THEN BEGIN 1 WHILE REPEAT 0 IF THEN");
+
+        assert_eq!(got.unwrap(), "1 ELSE#1 JEQ 2 THEN#1 JMP :ELSE#1 :BEGIN#2 0 BEGIN#2 JEQ :THEN#1 \
+:BEGIN#3 1 REPEAT#3 JEQ BEGIN#3 JMP :REPEAT#3 0 ELSE#4 JEQ :ELSE#4");
+    }
+
+    #[test]
+    fn executes_nested_constructs() {
+        // NOTE: sums odd numbers below 10
+        let text = "
+            0 10
+            BEGIN DUP WHILE
+                1 SUB
+                DUP 2 MOD IF SWAP OVER ADD SWAP ELSE THEN
+            REPEAT
+            DROP HALT";
+
+        assert_eq!(run(text), 25);
+    }
+
+    #[test]
+    fn keeps_local_label_scope() {
+        assert_eq!(run(":Main 1 IF .a JMP THEN 2 HALT :.a 3 HALT"), 3);
+    }
+
+    #[test]
+    fn error_on_unbalanced_nesting() {
+        assert_eq!(lowered("1 IF BEGIN THEN").unwrap_err().to_string(), "test:1:12: THEN doesn't match BEGIN\n    BEGIN opened here: test:1:6");
+        assert_eq!(lowered("BEGIN 1 IF UNTIL").unwrap_err().to_string(), "test:1:12: UNTIL doesn't match IF\n    IF opened here: test:1:9");
+        assert_eq!(lowered("1 ELSE").unwrap_err().to_string(), "test:1:3: ELSE without matching IF");
+        assert_eq!(lowered("REPEAT").unwrap_err().to_string(), "test:1:1: REPEAT without matching WHILE");
+        assert_eq!(lowered("1 IF ELSE ELSE THEN").unwrap_err().to_string(), "test:1:11: ELSE doesn't match IF\n    IF opened here: test:1:3");
+        assert_eq!(lowered("BEGIN 1 WHILE\n").unwrap_err().to_string(), "test:1:9: unterminated WHILE, expected REPEAT");
+        assert_eq!(lowered("1 IF").unwrap_err().to_string(), "test:1:3: unterminated IF, expected THEN");
+    }
+}
@@ -0,0 +1,227 @@
+use anyhow::Error;
+
+use crate::models::error::{Errors, SourceError};
+use crate::models::lint::Warning;
+use crate::models::token::Position;
+use super::tokenize::split_line;
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Error or warning with positions to show source lines of.
+#[derive(PartialEq, Debug)]
+pub struct Report {
+    /// `error`, `warning[lint]` or `error[lint]`.
+    pub severity: String,
+    pub message: String,
+    pub position: Option<Position>,
+    /// Other places related to report, like first declaration of duplicate label.
+    pub labels: Vec<(Position, String)>,
+    pub notes: Vec<String>,
+}
+
+impl From<&Warning> for Report {
+    fn from(warning: &Warning) -> Self {
+        Report{
+            severity: format!("warning[{}]", warning.lint),
+            message: warning.message.clone(),
+            position: Some(warning.position.clone()),
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+}
+
+/// Returns report for each error collected in `err`, message of report includes causes.
+/// Errors without [`SourceError`] on top are reported without position.
+pub fn reports(err: &Error) -> Vec<Report> {
+    Errors::split(err).into_iter()
+                      .map(|err| match SourceError::of(err) {
+                          Some(source) => Report{
+                              severity: source.lint.map_or("error".to_string(), |lint| format!("error[{lint}]")),
+                              message: err.chain()
+                                          .skip(1)
+                                          .fold(source.message.clone(), |message, cause| format!("{message}: {cause}")),
+                              position: Some(source.position.clone()),
+                              labels: source.labels.clone(),
+                              notes: source.notes.clone(),
+                          },
+                          None => Report{
+                              severity: "error".to_string(),
+                              message: format!("{err:#}"),
+                              position: None,
+                              labels: vec![],
+                              notes: vec![],
+                          },
+                      })
+                      .collect()
+}
+
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn paint(&self, code: &str, text: &str) -> String {
+        match self.color {
+            true => format!("{code}{text}{RESET}"),
+            false => text.to_string(),
+        }
+    }
+}
+
+/// Appends source line at `pos` underlined with `mark`, nothing if source is not available.
+fn snippet(res: &mut String, pos: &Position, mark: char, label: &str, width: usize, source: &dyn Fn(&str) -> Option<String>, style: &Style) {
+    let gutter = style.paint(BLUE, &format!("{:width$} |", ""));
+    let Some(line) = source(&pos.filename).and_then(|text| text.lines().nth(pos.line.wrapping_sub(1)).map(str::to_string)) else {
+        return
+    };
+    let length = split_line(&line).0
+                                  .into_iter()
+                                  .find(|(column, _)| *column == pos.column)
+                                  .map_or(1, |(_, text)| text.chars().count());
+    let underline = format!("{}{}", mark.to_string().repeat(length), if label.is_empty() { String::new() } else { format!(" {label}") });
+    let code = if mark == '^' { RED } else { BLUE };
+    res.push_str(&format!("{gutter}\n"));
+    res.push_str(&format!("{} {line}\n", style.paint(BLUE, &format!("{:>width$} |", pos.line))));
+    res.push_str(&format!("{gutter} {}{}\n", " ".repeat(pos.column.saturating_sub(1)), style.paint(code, &underline)));
+}
+
+fn render_report(report: &Report, source: &dyn Fn(&str) -> Option<String>, style: &Style) -> String {
+    let code = if report.severity.starts_with("warning") { YELLOW } else { RED };
+    let mut res = format!("{}{}\n", style.paint(code, &report.severity), style.paint(BOLD, &format!(": {}", report.message)));
+    let mut labels = vec![];
+    if let Some(pos) = &report.position {
+        let mut body = &pos.expansion;
+        while let Some(pos) = body {
+            labels.push((pos.as_ref(), "in this macro body"));
+            body = &pos.expansion;
+        }
+    }
+    labels.extend(report.labels.iter().map(|(pos, label)| (pos, label.as_str())));
+    let width = report.position.iter()
+                               .chain(labels.iter().map(|(pos, _)| *pos))
+                               .map(|pos| pos.line.to_string().len())
+                               .max()
+                               .unwrap_or(1);
+    if let Some(pos) = &report.position {
+        let location = Position{expansion: None, ..pos.clone()};
+        res.push_str(&format!("{}{location}\n", style.paint(BLUE, &format!("{:width$}--> ", ""))));
+        snippet(&mut res, &location, '^', "", width, source, style);
+    }
+    for (pos, label) in labels {
+        let location = Position{expansion: None, ..pos.clone()};
+        res.push_str(&format!("{}{location}\n", style.paint(BLUE, &format!("{:width$}::: ", ""))));
+        snippet(&mut res, &location, '-', label, width, source, style);
+    }
+    for note in &report.notes {
+        res.push_str(&format!("{} note: {note}\n", style.paint(BLUE, &format!("{:width$} =", ""))));
+    }
+    res
+}
+
+/// Renders reports in rustc style with source snippets, reading sources with `source`.
+pub fn render(reports: &[Report], source: &dyn Fn(&str) -> Option<String>, color: bool) -> String {
+    let style = Style{color};
+    reports.iter()
+           .map(|report| render_report(report, source, &style))
+           .collect::<Vec<_>>()
+           .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    fn source(name: &str) -> Option<String> {
+        match name {
+            "main" => Some(":Main 1 Loop JMP\n:Main HALT".to_string()),
+            "lib" => Some("%macro twice\n    DUP ADD\n%endm".to_string()),
+            "my dir/a:b" => Some("HALT".to_string()),
+            _ => None,
+        }
+    }
+
+    fn pos(filename: &str, line: usize, column: usize) -> Position {
+        Position{filename: filename.to_string(), line, column, expansion: None}
+    }
+
+    #[test]
+    fn renders_error_with_source_line_and_secondary_label() {
+        let err = SourceError::new(pos("main", 2, 1), "label declared twice: Main".to_string())
+            .with_label(pos("main", 1, 1), "first declared here");
+
+        let got = render(&reports(&err.into()), &source, false);
+
+        assert_eq!(got, "\
+error: label declared twice: Main
+ --> main:2:1
+  |
+2 | :Main HALT
+  | ^^^^^
+ ::: main:1:1
+  |
+1 | :Main 1 Loop JMP
+  | ----- first declared here
+");
+    }
+
+    #[test]
+    fn renders_warnings_macro_bodies_and_notes() {
+        let warning = Warning{lint: "unused_label", position: pos("main", 1, 9), message: "label Loop is never used".to_string()};
+        let failed = SourceError::new(Position{expansion: Some(Box::new(pos("lib", 2, 9))), ..pos("main", 1, 1)},
+                                      "failed to execute ident instruction ADD".to_string())
+            .with_note("at address 257")
+            .caused_by(anyhow!("stack underflow"));
+        let err = Errors(vec![failed.into(), Err::<(), _>(anyhow!("missing")).context("failed to read file").unwrap_err()]);
+
+        let mut all = vec![Report::from(&warning)];
+        all.extend(reports(&err.into()));
+
+        let got = render(&all, &source, false);
+
+        assert_eq!(got, "\
+warning[unused_label]: label Loop is never used
+ --> main:1:9
+  |
+1 | :Main 1 Loop JMP
+  |         ^^^^
+
+error: failed to execute ident instruction ADD: stack underflow
+ --> main:1:1
+  |
+1 | :Main 1 Loop JMP
+  | ^^^^^
+ ::: lib:2:9
+  |
+2 |     DUP ADD
+  |         --- in this macro body
+  = note: at address 257
+
+error: failed to read file: missing
+");
+    }
+
+    #[test]
+    fn renders_files_with_spaces_and_colons_in_name() {
+        let err = SourceError::new(pos("my dir/a:b", 1, 1), "no".to_string());
+
+        let got = render(&reports(&err.into()), &source, false);
+
+        assert_eq!(got, "error: no\n --> my dir/a:b:1:1\n  |\n1 | HALT\n  | ^^^^\n");
+    }
+
+    #[test]
+    fn colors_severity_and_gutter() {
+        let err = SourceError::new(pos("main", 1, 8), "no HALT".to_string()).with_lint("missing_halt");
+
+        let got = render(&reports(&err.into()), &source, true);
+
+        assert!(got.starts_with("\x1b[1;31merror[missing_halt]\x1b[0m\x1b[1m: no HALT\x1b[0m\n\x1b[1;34m --> \x1b[0mmain:1:8\n"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, Result};
+
+use crate::models::error::CollectErrors as _;
+use crate::models::command::Opcode;
+use super::command::get_command;
+
+const JUMPS: [&str; 8] = ["JMP", "JGE", "JNE", "JGT", "JLE", "JEQ", "JLT", "CALL"];
+
+fn mnemonics(opcode: Opcode) -> Option<&'static [&'static str]> {
+    get_command(opcode).map(|x| x.mnemonics)
+}
+
+fn is_jump(opcode: Opcode) -> bool {
+    mnemonics(opcode).is_some_and(|x| x.iter().any(|mnemonic| JUMPS.contains(mnemonic)))
+}
+
+/// Parses whitespace or comma separated list of integers.
+pub fn parse_opcodes(text: &str) -> Result<Vec<Opcode>> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse().map_err(|err| anyhow!("invalid opcode \"{x}\": {err}")))
+        .collect_errors()
+}
+
+/// Reconstructs source from opcodes of program loaded at address 256.
+/// Code addresses pushed right before jump or call become `Lnnn` labels.
+pub fn disassemble(opcodes: &[Opcode]) -> String {
+    let end = 256 + opcodes.len() as Opcode;
+    let is_target = |i: usize| (256..end).contains(&opcodes[i]) && opcodes.get(i+1).copied().is_some_and(is_jump);
+    let targets: BTreeSet<Opcode> = (0..opcodes.len()).filter(|i| is_target(*i))
+                                                      .map(|i| opcodes[i])
+                                                      .collect();
+
+    let mut res = String::new();
+    for (i, opcode) in opcodes.iter().enumerate() {
+        let address = 256 + i as Opcode;
+        if targets.contains(&address) {
+            res.push_str(&format!(":L{address}\n"));
+        }
+        let text = match mnemonics(*opcode) {
+            // NOTE: JMP and RET share opcode, jump is recognized by target pushed before it
+            Some(["SETIP", "JMP", "RET"]) if i > 0 && is_target(i-1) => "JMP".to_string(),
+            Some(["SETIP", "JMP", "RET"]) => "RET".to_string(),
+            Some(mnemonics) => mnemonics[0].to_string(),
+            None if is_target(i) => format!("L{opcode}"),
+            None => opcode.to_string(),
+        };
+        res.push_str(&format!("    {text}\n"));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::assembly::{self, Options, TextFile};
+
+    #[test]
+    fn disassembles_opcodes() {
+        let got = disassemble(&parse_opcodes("5, 259 -31 0 -37\n-1 -100 -13 258 -13 256 -22").unwrap());
+
+        assert_eq!(got, ":L256
+    5
+    L259
+:L258
+    CALL
+:L259
+    0
+    HALT
+    ADD
+    -100
+    RET
+    L258
+    JMP
+    L256
+    JEQ
+");
+    }
+
+    #[test]
+    fn renders_out_of_range_opcodes_as_literals() {
+        let opcodes = parse_opcodes("-9223372036854775808 9223372036854775807 -45 -1").unwrap();
+
+        let got = disassemble(&opcodes);
+
+        assert_eq!(got, "    -9223372036854775808\n    9223372036854775807\n    -45\n    ADD\n");
+        let reassembled = assembly::assembly(&[TextFile{name: "disasm".to_owned(), text: got}], &Options::default()).unwrap();
+        assert_eq!(reassembled.iter().map(|x| x.opcode).collect::<Vec<_>>(), opcodes);
+    }
+
+    #[test]
+    fn disassembled_program_assembles_to_same_opcodes() {
+        let text = std::fs::read_to_string("main.asm").unwrap();
+        let opcodes = assembly::assembly(&[TextFile{name: "main.asm".to_owned(), text}], &Options::default()).unwrap()
+                                       .iter()
+                                       .map(|x| x.opcode)
+                                       .collect::<Vec<_>>();
+
+        let got = assembly::assembly(&[TextFile{name: "disasm".to_owned(), text: disassemble(&opcodes)}], &Options::default());
+
+        assert_eq!(got.unwrap().iter().map(|x| x.opcode).collect::<Vec<_>>(), opcodes);
+    }
+}
@@ -0,0 +1,171 @@
+use std::{collections::HashMap, iter::Peekable, str::CharIndices};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::models::command::Opcode;
+use crate::models::expression::{BinaryOperator, Expression};
+use super::{tokenize, visibility};
+
+struct Parser<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn take_word(&mut self) -> &str {
+        let start = self.chars.peek().map_or(self.text.len(), |(i, _)| *i);
+        while self.chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_' || *c == '.').is_some() {}
+        let end = self.chars.peek().map_or(self.text.len(), |(i, _)| *i);
+        &self.text[start..end]
+    }
+
+    fn binary(&mut self, operators: &[(char, BinaryOperator)], operand: fn(&mut Self) -> Result<Expression>) -> Result<Expression> {
+        let mut res = operand(self)?;
+        while let Some(&(_, op)) = self.peek().and_then(|c| operators.iter().find(|(x, _)| *x == c)) {
+            self.chars.next();
+            res = Expression::Binary(op, Box::new(res), Box::new(operand(self)?));
+        }
+        Ok(res)
+    }
+
+    fn sum(&mut self) -> Result<Expression> {
+        self.binary(&[('+', BinaryOperator::Add), ('-', BinaryOperator::Sub)], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expression> {
+        self.binary(&[('*', BinaryOperator::Mul), ('/', BinaryOperator::Div), ('%', BinaryOperator::Rem)], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expression> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(Expression::Negate(Box::new(self.unary()?)))
+            },
+            Some('+') => {
+                self.chars.next();
+                self.unary()
+            },
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression> {
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let res = self.sum()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(res)
+                    },
+                    _ => Err(anyhow!("expected closing parenthesis")),
+                }
+            },
+            Some('0'..='9') => Ok(Expression::Integer(tokenize::parse_integer(self.take_word())?)),
+            Some('a'..='z' | 'A'..='Z' | '_' | '.') => Ok(Expression::Symbol(self.take_word().to_string())),
+            Some(x) => Err(anyhow!("unexpected symbol: \"{x}\"")),
+            None => Err(anyhow!("unexpected end of expression")),
+        }
+    }
+}
+
+/// Parses expression of integers and symbols with `+ - * / %` operators and parentheses.
+/// Symbols can't contain `-` here, unlike idents.
+pub fn parse(text: &str) -> Result<Expression> {
+    let mut parser = Parser{text, chars: text.char_indices().peekable()};
+    let res = parser.sum()?;
+    if let Some(x) = parser.peek() {
+        bail!("unexpected symbol: \"{x}\"")
+    }
+    Ok(res)
+}
+
+pub fn evaluate(expression: &Expression, symbols: &HashMap<&str, Opcode>) -> Result<i64> {
+    match expression {
+        Expression::Integer(i) => Ok(*i),
+        Expression::Symbol(name) => symbols.get(name.as_str())
+                                           .copied()
+                                           .ok_or_else(|| anyhow!("undefined symbol in expression: \"{}\"", visibility::source_name(name))),
+        Expression::Negate(x) => evaluate(x, symbols)?.checked_neg()
+                                                      .ok_or_else(|| anyhow!("overflow in expression")),
+        Expression::Binary(op, x, y) => {
+            let (x, y) = (evaluate(x, symbols)?, evaluate(y, symbols)?);
+            if matches!(op, BinaryOperator::Div | BinaryOperator::Rem) && y == 0 {
+                bail!("division by zero in expression")
+            }
+            match op {
+                BinaryOperator::Add => x.checked_add(y),
+                BinaryOperator::Sub => x.checked_sub(y),
+                BinaryOperator::Mul => x.checked_mul(y),
+                BinaryOperator::Div => x.checked_div(y),
+                BinaryOperator::Rem => x.checked_rem(y),
+            }.ok_or_else(|| anyhow!("overflow in expression"))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_with_precedence() {
+        let got = parse("Table+Index*2").unwrap();
+
+        assert_eq!(got, Expression::Binary(
+            BinaryOperator::Add,
+            Box::new(Expression::Symbol("Table".to_string())),
+            Box::new(Expression::Binary(
+                BinaryOperator::Mul,
+                Box::new(Expression::Symbol("Index".to_string())),
+                Box::new(Expression::Integer(2)),
+            )),
+        ));
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let symbols = HashMap::from([("End", 300), ("Start", 260), ("PROGRAM_SIZE", 400)]);
+
+        let got = ["(End-Start)", "PROGRAM_SIZE+100", "-(0x10 + 1_0) * 3 % 7", "(End - Start) / 2"]
+            .map(|x| evaluate(&parse(x).unwrap(), &symbols).unwrap());
+
+        assert_eq!(got, [40, 500, -1, 20]);
+    }
+
+    #[test]
+    fn error_on_syntax() {
+        let got = ["(1+2", "1+", "1 2", "1+$"].map(|x| parse(x).unwrap_err().to_string());
+
+        assert_eq!(got, [
+            "expected closing parenthesis",
+            "unexpected end of expression",
+            "unexpected symbol: \"2\"",
+            "unexpected symbol: \"$\"",
+        ]);
+    }
+
+    #[test]
+    fn error_on_evaluation() {
+        let symbols = HashMap::from([("Big", i64::MAX)]);
+
+        let got = ["Big+1", "Nope*2", "1/(Big-Big)"].map(|x| evaluate(&parse(x).unwrap(), &symbols).unwrap_err().to_string());
+
+        assert_eq!(got, [
+            "overflow in expression",
+            "undefined symbol in expression: \"Nope\"",
+            "division by zero in expression",
+        ]);
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{bail, Result};
+
+use crate::models::token::{Position, Token};
+use super::tokenize::{self, split_line};
+
+const INDENT: &str = "    ";
+/// Minimal column of trailing comments, as in main.asm.
+const COMMENT_COLUMN: usize = 32;
+
+struct Line<'a> {
+    indent: &'static str,
+    code: String,
+    comment: Option<&'a str>,
+    /// Index of routine, comments are aligned within it.
+    routine: usize,
+}
+
+/// Routines start at global label declarations and `%proc` directives.
+fn starts_routine(token: &str) -> bool {
+    (token.starts_with(':') && !token.starts_with(":.")) || token == "%proc"
+}
+
+fn parse_lines(text: &str) -> Vec<Line<'_>> {
+    let mut routine = 0;
+    let mut res: Vec<Line> = text.lines()
+                                 .map(|line| {
+                                     let (tokens, comment) = split_line(line);
+                                     let code = tokens.iter().map(|(_, token)| *token).collect::<Vec<_>>().join(" ");
+                                     let indent = match tokens.first() {
+                                         Some((_, first)) if starts_routine(first) => {
+                                             routine += 1;
+                                             ""
+                                         },
+                                         Some((_, first)) if first.starts_with('%') => "",
+                                         Some(_) if routine > 0 => INDENT,
+                                         _ => "",
+                                     };
+                                     Line{indent, code, comment: comment.map(str::trim_end), routine}
+                                 })
+                                 .collect();
+
+    // NOTE: comment-only lines are indented as the next line with code, or as routine body at end of file
+    let mut next_indent = None;
+    for line in res.iter_mut().rev() {
+        if !line.code.is_empty() {
+            next_indent = Some(line.indent);
+        } else if line.comment.is_some() {
+            line.indent = next_indent.unwrap_or(if line.routine > 0 { INDENT } else { "" });
+        }
+    }
+    res
+}
+
+fn comment_column(lines: &[Line], routine: usize) -> usize {
+    lines.iter()
+         .filter(|x| x.routine == routine && !x.code.is_empty() && x.comment.is_some())
+         .map(|x| x.indent.len() + x.code.chars().count() + 1)
+         .fold(COMMENT_COLUMN, usize::max)
+}
+
+/// Formats source: code of each routine is indented under its `:Label`,
+/// tokens are separated by single space and trailing comments are aligned into a column.
+pub fn format(text: &str) -> String {
+    let lines = parse_lines(text);
+    let mut res = String::new();
+    for line in &lines {
+        let mut formatted = format!("{}{}", line.indent, line.code);
+        if let Some(comment) = line.comment {
+            if !line.code.is_empty() {
+                let column = comment_column(&lines, line.routine);
+                formatted = format!("{formatted:<column$}");
+            }
+            formatted.push_str(comment);
+        }
+        res.push_str(&formatted);
+        res.push('\n');
+    }
+    res
+}
+
+fn without_columns(tokens: Vec<Token>) -> Vec<Token> {
+    tokens.into_iter()
+          .map(|x| {
+              let pos = Position{column: 0, ..x.position().clone()};
+              x.with_position(pos)
+          })
+          .collect()
+}
+
+/// Formats source and checks that it still has the same tokens on the same lines.
+pub fn format_checked(text: &str, filename: &str) -> Result<String> {
+    let res = format(text);
+    if without_columns(tokenize::tokenize(text, filename)?) != without_columns(tokenize::tokenize(&res, filename)?) {
+        bail!("{filename}: formatting changed tokens of file")
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_routines_and_comments() {
+        let text = "Size  EQU 2
+  Main   CALL  ; call
+;;; NOTE: main routine
+ :Main
+IN  ; c
+      DUP \"a ; b\" ;; c c
+:.loop Size    JMP
+  ; end
+";
+
+        let got = format_checked(text, "test");
+
+        assert_eq!(got.unwrap(), "Size EQU 2
+Main CALL                       ; call
+;;; NOTE: main routine
+:Main
+    IN                          ; c
+    DUP \"a ; b\"                 ;; c c
+    :.loop Size JMP
+    ; end
+");
+    }
+
+    #[test]
+    fn pushes_comment_column_after_longest_line() {
+        let text = ":A\nPROGRAM_SIZE PROGRAM_SIZE PROGRAM_SIZE ; a\n1 ; b";
+
+        let got = format(text);
+
+        assert_eq!(got, ":A
+    PROGRAM_SIZE PROGRAM_SIZE PROGRAM_SIZE ; a
+    1                                      ; b
+");
+    }
+
+    #[test]
+    fn keeps_tokens_of_main_asm() {
+        let text = std::fs::read_to_string("main.asm").unwrap();
+
+        let got = format_checked(&text, "main.asm");
+
+        assert!(got.is_ok());
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+
+use crate::models::command::Instruction;
+use crate::models::error::{CollectErrors as _, SourceError, error_at, bail_at};
+use crate::models::token::{Position, Token};
+use super::assembly::{self, TextFile};
+use super::control;
+use super::tokenize::{emit, parse_integer};
+
+/// Cell holding return stack pointer, variables and return stack follow it after the code.
+const RP: &str = "(PROGRAM_SIZE)";
+
+/// Words compiled to fixed stack assembly. Comparisons give Forth flags, -1 for true.
+const WORDS: &[(&str, &str)] = &[
+    ("+", "ADD"), ("-", "SUB"), ("*", "MUL"), ("/", "DIV"), ("MOD", "MOD"), ("NEGATE", "NEG"),
+    ("AND", "BITAND"), ("OR", "BITOR"), ("XOR", "BITXOR"), ("INVERT", "BITNOT"),
+    ("DUP", "DUP"), ("DROP", "DROP"), ("SWAP", "SWAP"), ("OVER", "OVER"), ("ROT", "ROT"), ("NIP", "SDROP"), ("2DROP", "DROP2"),
+    ("@", "LOAD"), ("!", "SWAP SAVE"), ("EMIT", "OUT"), ("KEY", "IN"), ("CR", "10 OUT"),
+    ("0=", "IF 0 ELSE 1 NEG THEN"),
+    ("=", "CMP IF 0 ELSE 1 NEG THEN"),
+    ("<>", "CMP IF 1 NEG ELSE 0 THEN"),
+    ("<", "CMP 1 ADD IF 0 ELSE 1 NEG THEN"),
+    (">", "CMP 1 SUB IF 0 ELSE 1 NEG THEN"),
+];
+
+/// Words handled by the compiler itself.
+const COMPILER_WORDS: &[&str] = &[
+    ":", ";", "EXIT", "RECURSE", "VARIABLE", "IF", "ELSE", "THEN", "BEGIN", "UNTIL", "WHILE", "REPEAT",
+    "DO", "LOOP", "I", "J", ">R", "R>", "R@",
+];
+
+fn is_builtin(name: &str) -> bool {
+    COMPILER_WORDS.contains(&name) || WORDS.iter().any(|(word, _)| *word == name)
+}
+
+/// Whether word is meant as number, like `42`, `-7` or `0xFF`.
+fn is_number(word: &str) -> bool {
+    word.trim_start_matches(['-', '+']).starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Definitions compiled together with the program.
+const PRELUDE: &str = "
+: (.) DUP 9 > IF DUP 10 / (.) THEN 10 MOD 48 + EMIT ;
+: . DUP 0 < IF 45 EMIT NEGATE THEN (.) 32 EMIT ;
+";
+
+fn push_r() -> String {
+    format!("{RP} LOAD SWAP SAVE {RP} {RP} LOAD 1 ADD SAVE")
+}
+
+fn pop_r() -> String {
+    format!("{RP} {RP} LOAD 1 SUB SAVE {RP} LOAD LOAD")
+}
+
+/// Splits text into words with their positions, skipping `\ ...` and `( ... )` comments.
+/// Words keep their spelling for messages, compiler compares them case-insensitively.
+fn split_words(file: &TextFile) -> Vec<(String, Position)> {
+    let mut res = vec![];
+    let mut in_comment = false;
+    for (i, line) in file.text.lines().enumerate() {
+        let mut start = None;
+        for (j, c) in line.char_indices().chain([(line.len(), ' ')]) {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(j),
+                (true, Some(s)) => {
+                    start = None;
+                    let word = &line[s..j];
+                    if in_comment {
+                        in_comment = !word.ends_with(')');
+                    } else if word == "\\" {
+                        break
+                    } else if word == "(" {
+                        in_comment = true;
+                    } else {
+                        let pos = Position{filename: file.name.clone(), line: i+1, column: line[..s].chars().count()+1, expansion: None};
+                        res.push((word.to_string(), pos));
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+    res
+}
+
+fn label(name: &str) -> String {
+    format!("forth#{name}")
+}
+
+fn emit_call(out: &mut Vec<Token>, name: &str, pos: &Position) {
+    out.extend([Token::Ident(label(name), pos.clone()), Token::Ident("CALL".to_string(), pos.clone())]);
+}
+
+struct Compiler {
+    main: Vec<Token>,
+    definitions: Vec<Token>,
+    /// Labels of defined words.
+    words: HashSet<String>,
+    /// Offsets of variables from `RP`.
+    variables: HashMap<String, usize>,
+}
+
+impl Compiler {
+    /// Declares words and variables first, so that words can be used before their definitions.
+    fn declare(&mut self, words: &[(String, Position)]) -> Result<()> {
+        let mut errors: Vec<Result<()>> = vec![];
+        for pair in words.windows(2) {
+            let ((word, _), (name, pos)) = (&pair[0], &pair[1]);
+            let key = name.to_uppercase();
+            let declared = match word.to_uppercase().as_str() {
+                ":" => self.words.insert(key.clone()) && !self.variables.contains_key(&key),
+                "VARIABLE" => {
+                    let offset = self.variables.len() + 1;
+                    self.variables.insert(key.clone(), offset).is_none() && !self.words.contains(&key)
+                },
+                _ => continue,
+            };
+            if is_builtin(&key) {
+                errors.push(Err(error_at!(pos, "builtin word can't be redefined: {name}")));
+            } else if !declared {
+                errors.push(Err(error_at!(pos, "word defined twice: {name}")));
+            }
+        }
+        errors.into_iter().collect_errors()
+    }
+
+    fn compile(&mut self, words: Vec<(String, Position)>) -> Result<()> {
+        let mut errors: Vec<Result<()>> = vec![];
+        let mut definition: Option<(String, Position)> = None;
+        // NOTE: control structures are checked here, so that DO and LOOP errors aren't reported as BEGIN and UNTIL
+        let mut constructs: Vec<(&str, Position)> = vec![];
+        let mut words = words.into_iter();
+        while let Some((word, pos)) = words.next() {
+            let key = word.to_uppercase();
+            let in_definition = definition.is_some() || key == ":";
+            let mut out = vec![];
+            let opening = match key.as_str() {
+                "ELSE" | "THEN" => Some("IF"),
+                "UNTIL" | "WHILE" => Some("BEGIN"),
+                "REPEAT" => Some("WHILE"),
+                "LOOP" => Some("DO"),
+                _ => None,
+            };
+            if let Some(opening) = opening {
+                match constructs.pop() {
+                    Some((construct, construct_pos)) if construct == opening => match key.as_str() {
+                        "ELSE" => constructs.push((construct, construct_pos)),
+                        "WHILE" => constructs.push(("WHILE", pos.clone())),
+                        _ => {},
+                    },
+                    Some((construct, construct_pos)) =>
+                        return Err(SourceError::new(pos, format!("{word} doesn't match {construct}"))
+                                       .with_label(construct_pos, &format!("{construct} opened here"))
+                                       .into()),
+                    None => bail_at!(pos, "{word} without matching {opening}"),
+                }
+            }
+            match key.as_str() {
+                ":" => {
+                    if let Some((_, def_pos)) = &definition {
+                        return Err(SourceError::new(pos, "nested definition".to_string())
+                                       .with_label(def_pos.clone(), "definition started here")
+                                       .into())
+                    }
+                    let (name, name_pos) = words.next().ok_or_else(|| error_at!(pos, "expected word name after :"))?;
+                    let name = name.to_uppercase();
+                    out.push(Token::Declaration(label(&name), name_pos));
+                    emit(&mut out, &push_r(), &pos)?;
+                    definition = Some((name, pos.clone()));
+                },
+                ";" => {
+                    if definition.take().is_none() {
+                        bail_at!(pos, "; without matching :")
+                    }
+                    if let Some((construct, construct_pos)) = constructs.pop() {
+                        return Err(SourceError::new(construct_pos, format!("unterminated {construct}"))
+                                       .with_label(pos, "definition ends here")
+                                       .into())
+                    }
+                    emit(&mut out, &format!("{} RET", pop_r()), &pos)?;
+                },
+                "EXIT" if definition.is_some() => emit(&mut out, &format!("{} RET", pop_r()), &pos)?,
+                "RECURSE" if definition.is_some() => {
+                    let (name, _) = definition.as_ref().unwrap();
+                    emit_call(&mut out, name, &pos);
+                },
+                "VARIABLE" => {
+                    words.next().ok_or_else(|| error_at!(pos, "expected variable name after {word}"))?;
+                },
+                "IF" | "BEGIN" => {
+                    constructs.push((if key == "IF" { "IF" } else { "BEGIN" }, pos.clone()));
+                    emit(&mut out, &key, &pos)?;
+                },
+                "DO" => {
+                    constructs.push(("DO", pos.clone()));
+                    emit(&mut out, &format!("SWAP {} {} BEGIN", push_r(), push_r()), &pos)?;
+                },
+                "ELSE" | "THEN" | "WHILE" | "UNTIL" | "REPEAT" => emit(&mut out, &key, &pos)?,
+                // NOTE: incremented index is compared with limit below it on return stack
+                "LOOP" => emit(&mut out, &format!("{} 1 ADD DUP {RP} LOAD 1 SUB LOAD CMP 1 ADD SWAP {} UNTIL {} {} DROP2",
+                                                  pop_r(), push_r(), pop_r(), pop_r()), &pos)?,
+                "I" | "J" => {
+                    if !constructs.iter().any(|(construct, _)| *construct == "DO") {
+                        bail_at!(pos, "{word} outside of DO loop")
+                    }
+                    emit(&mut out, &format!("{RP} LOAD {} SUB LOAD", if key == "I" { 1 } else { 3 }), &pos)?;
+                },
+                ">R" => emit(&mut out, &push_r(), &pos)?,
+                "R>" => emit(&mut out, &pop_r(), &pos)?,
+                "R@" => emit(&mut out, &format!("{RP} LOAD 1 SUB LOAD"), &pos)?,
+                _ => if let Some((_, text)) = WORDS.iter().find(|(name, _)| *name == key) {
+                    emit(&mut out, text, &pos)?;
+                } else if self.words.contains(&key) {
+                    emit_call(&mut out, &key, &pos);
+                } else if let Some(offset) = self.variables.get(&key) {
+                    emit(&mut out, &format!("(PROGRAM_SIZE+{offset})"), &pos)?;
+                } else if is_number(&word) {
+                    match parse_integer(&word) {
+                        Ok(value) if value >= 0 => emit(&mut out, &value.to_string(), &pos)?,
+                        Ok(value) => match value.checked_neg() {
+                            Some(negated) => emit(&mut out, &format!("{negated} NEG"), &pos)?,
+                            None => errors.push(Err(error_at!(pos, "number out of range: {word}"))),
+                        },
+                        Err(err) => errors.push(Err(error_at!(pos, "invalid number: {word}: {err}"))),
+                    }
+                } else {
+                    errors.push(Err(error_at!(pos, "undefined word: {word}")));
+                },
+            }
+            match in_definition {
+                true => self.definitions.extend(out),
+                false => self.main.extend(out),
+            }
+        }
+        if let Some((_, pos)) = definition {
+            bail_at!(pos, "unterminated definition, expected ;")
+        }
+        if let Some((construct, pos)) = constructs.pop() {
+            bail_at!(pos, "unterminated {construct}")
+        }
+        errors.into_iter().collect_errors()
+    }
+}
+
+/// Compiles Forth program into instructions for the VM. Data stack is the VM stack,
+/// return stack used by word calls and `DO` loops lives in memory after the code.
+pub fn compile(files: &[TextFile]) -> Result<Vec<Instruction>> {
+    let mut compiler = Compiler{main: vec![], definitions: vec![], words: HashSet::new(), variables: HashMap::new()};
+    let prelude = TextFile{name: "prelude".to_string(), text: PRELUDE.to_string()};
+    let words = [&prelude].into_iter()
+                          .chain(files)
+                          .map(split_words)
+                          .collect::<Vec<_>>();
+    compiler.declare(&words.concat())?;
+    words.into_iter()
+         .map(|words| compiler.compile(words))
+         .collect_errors::<()>()?;
+
+    let start = Position{filename: files.first().map_or(String::new(), |x| x.name.clone()), line: 1, column: 1, expansion: None};
+    let init = format!("{RP} (PROGRAM_SIZE+{}) SAVE", compiler.variables.len() + 1);
+    let mut tokens = vec![];
+    emit(&mut tokens, &init, &start)?;
+    tokens.extend(compiler.main);
+    emit(&mut tokens, "0 HALT", &start)?;
+    tokens.extend(compiler.definitions);
+    assembly::assemble_tokens(&control::lower(tokens)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::stdio::testing;
+
+    fn output(text: &str) -> Result<String> {
+        let instructions = compile(&[TextFile{name: "test".to_string(), text: text.to_string()}])?;
+        Ok(testing::run(instructions, "")?.0)
+    }
+
+    #[test]
+    fn prints_numbers_and_characters() {
+        assert_eq!(output("42 . -7 . 0 . 72 EMIT 105 emit CR").unwrap(), "42 -7 0 Hi\n");
+    }
+
+    #[test]
+    fn parses_numbers_as_assembler_does() {
+        assert_eq!(output("0x10 . -0b11 . 1_000 . +5 .").unwrap(), "16 -3 1000 5 ");
+        assert_eq!(output("0xZZ .").unwrap_err().to_string(), "test:1:1: invalid number: 0xZZ: invalid digit in hexadecimal literal");
+    }
+
+    #[test]
+    fn compiles_definitions_with_recursion() {
+        let text = "
+            : fact ( n -- n! ) DUP 1 > IF DUP 1 - RECURSE * ELSE DROP 1 THEN ;
+            : squared DUP * ;  \\ words can be used before definition too
+            5 fact . 7 squared .";
+
+        assert_eq!(output(text).unwrap(), "120 49 ");
+    }
+
+    #[test]
+    fn runs_nested_loops_with_variables() {
+        let text = "
+            VARIABLE total
+            0 total !
+            3 0 DO 4 1 DO I J * total @ + total ! LOOP LOOP
+            total @ .
+            1 2 < . 2 1 < . 3 3 = . 3 3 <> .";
+
+        assert_eq!(output(text).unwrap(), "18 -1 0 -1 0 ");
+    }
+
+    #[test]
+    fn error_on_unbalanced_control_words() {
+        assert_eq!(output(": f 1 IF 2 LOOP ;").unwrap_err().to_string(), "test:1:12: LOOP doesn't match IF\n    IF opened here: test:1:7");
+        assert_eq!(output(": f 1 IF ;").unwrap_err().to_string(), "test:1:7: unterminated IF\n    definition ends here: test:1:10");
+        assert_eq!(output("1 THEN").unwrap_err().to_string(), "test:1:3: THEN without matching IF");
+        assert_eq!(output("I").unwrap_err().to_string(), "test:1:1: I outside of DO loop");
+        assert_eq!(output(": f 1").unwrap_err().to_string(), "test:1:1: unterminated definition, expected ;");
+        assert_eq!(output("foo 1 bar").unwrap_err().to_string(), "test:1:1: undefined word: foo\ntest:1:7: undefined word: bar");
+    }
+
+    #[test]
+    fn error_on_redefinition() {
+        assert_eq!(output(": DUP 42 EMIT ; 1 DUP . .").unwrap_err().to_string(), "test:1:3: builtin word can't be redefined: DUP");
+        assert_eq!(output(": loop ; VARIABLE i").unwrap_err().to_string(),
+                   "test:1:3: builtin word can't be redefined: loop\ntest:1:19: builtin word can't be redefined: i");
+        assert_eq!(output(": f ; VARIABLE f").unwrap_err().to_string(), "test:1:16: word defined twice: f");
+    }
+}
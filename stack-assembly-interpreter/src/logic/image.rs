@@ -0,0 +1,105 @@
+use anyhow::{bail, Result};
+
+use crate::models::command::Instruction;
+use crate::models::token::{Position, Token};
+use crate::models::vm::VM;
+use super::binary::{Reader, Writer};
+
+const MAGIC: &[u8; 4] = b"SAIM";
+const VERSION: u32 = 1;
+
+pub fn is_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Encodes program as opcodes, followed by tokens with source positions if `debug_info` is set.
+pub fn serialize(instructions: &[Instruction], debug_info: bool) -> Vec<u8> {
+    let mut writer = Writer::new(MAGIC, VERSION);
+    writer.usize(instructions.len());
+    for instruction in instructions {
+        writer.i64(instruction.opcode);
+    }
+    writer.u8(debug_info as u8);
+    if debug_info {
+        for instruction in instructions {
+            writer.token(&instruction.token);
+        }
+    }
+    writer.into_bytes()
+}
+
+/// Decodes image produced by [`serialize`].
+/// Without debug info, instructions get integer tokens positioned at `name`.
+pub fn deserialize(bytes: &[u8], name: &str) -> Result<Vec<Instruction>> {
+    let mut reader = Reader::new(bytes, MAGIC, VERSION)?;
+    let count = reader.usize()?;
+    if count > VM::MAX_CODE_SIZE {
+        bail!("image has {count} instructions, but only {} fit in memory", VM::MAX_CODE_SIZE)
+    }
+    let opcodes = (0..count).map(|_| reader.i64())
+                                      .collect::<Result<Vec<_>>>()?;
+    let debug_info = reader.u8()? != 0;
+    let res = opcodes.into_iter()
+                     .map(|opcode| -> Result<_> {
+                         let token = match debug_info {
+                             true => reader.token()?,
+                             false => Token::Integer(opcode, Position{filename: name.to_string(), ..Default::default()}),
+                         };
+                         Ok(Instruction{opcode, token})
+                     })
+                     .collect::<Result<Vec<_>>>()?;
+    if !reader.is_empty() {
+        bail!("unexpected data after end of image")
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::assembly::{self, Options, TextFile};
+
+    fn program() -> Vec<Instruction> {
+        let files = [TextFile{name: "main".to_owned(), text: String::from(":Loop 'a' OUT Loop JMP\nc\"hi\" (Loop+1)")}];
+        assembly::assembly(&files, &Options::default()).unwrap()
+    }
+
+    #[test]
+    fn roundtrips_with_debug_info() {
+        let instructions = program();
+
+        let got = deserialize(&serialize(&instructions, true), "a.img");
+
+        assert_eq!(got.unwrap(), instructions);
+    }
+
+    #[test]
+    fn roundtrips_opcodes_without_debug_info() {
+        let instructions = program();
+
+        let got = deserialize(&serialize(&instructions, false), "a.img").unwrap();
+
+        assert_eq!(got.iter().map(|x| x.opcode).collect::<Vec<_>>(), instructions.iter().map(|x| x.opcode).collect::<Vec<_>>());
+        assert_eq!(got[0].token, Token::Integer(97, Position{filename: "a.img".to_string(), ..Default::default()}));
+    }
+
+    #[test]
+    fn error_on_truncated_image() {
+        let bytes = serialize(&program(), true);
+
+        let got = deserialize(&bytes[..bytes.len()-1], "a.img");
+
+        assert_eq!(got.unwrap_err().to_string(), "unexpected end of file");
+    }
+
+    #[test]
+    fn error_on_too_many_instructions() {
+        let mut writer = Writer::new(MAGIC, VERSION);
+        writer.usize(1_000_000);
+        let bytes = writer.into_bytes();
+
+        let got = deserialize(&bytes, "a.img");
+
+        assert_eq!(got.unwrap_err().to_string(), "image has 1000000 instructions, but only 999744 fit in memory");
+    }
+}
@@ -0,0 +1,180 @@
+use std::{fs, io, path::{Component, Path, PathBuf}};
+
+use anyhow::{Error, Result};
+
+use crate::models::error::{error_at, bail_at, SourceContext as _, SourceError};
+use crate::models::token::{Position, Token};
+use super::tokenize;
+
+pub trait SourceLoader {
+    fn load(&self, path: &Path) -> io::Result<String>;
+}
+
+pub struct FsLoader;
+
+impl SourceLoader for FsLoader {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir if matches!(res.components().next_back(), Some(Component::Normal(_))) => {
+                res.pop();
+            },
+            _ => res.push(component),
+        }
+    }
+    res
+}
+
+struct Includer<'a> {
+    include_dirs: &'a [PathBuf],
+    loader: &'a dyn SourceLoader,
+    /// Files being included at the moment, with positions of their `%include` directives.
+    chain: Vec<(PathBuf, Option<Position>)>,
+}
+
+impl Includer<'_> {
+    fn tokenize(&mut self, text: &str, path: &Path) -> Result<Vec<Token>> {
+        let mut tokens = tokenize::tokenize(text, &path.to_string_lossy())?.into_iter();
+        let mut res = vec![];
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Directive(directive, pos) if directive == "include" => {
+                    let included = match tokens.next() {
+                        Some(Token::String(literal, _)) => tokenize::string_value(&literal)?,
+                        _ => bail_at!(pos, "expected quoted file path after %include"),
+                    };
+                    res.extend(self.include(&included, path, pos)?);
+                },
+                _ => res.push(token),
+            }
+        }
+        Ok(res)
+    }
+
+    fn include(&mut self, included: &str, from: &Path, pos: Position) -> Result<Vec<Token>> {
+        let (path, text) = self.load(included, from, &pos)?;
+        if self.chain.iter().any(|(x, _)| *x == path) {
+            return Err(self.cycle_error(included, &pos))
+        }
+        self.chain.push((path.clone(), Some(pos)));
+        let res = self.tokenize(&text, &path);
+        self.chain.pop();
+        res
+    }
+
+    /// Looks up `included` next to the including file, then in include directories.
+    fn load(&self, included: &str, from: &Path, pos: &Position) -> Result<(PathBuf, String)> {
+        let base = from.parent().unwrap_or(Path::new(""));
+        let candidates = std::iter::once(base).chain(self.include_dirs.iter().map(PathBuf::as_path))
+                                              .map(|dir| normalize(&dir.join(included)));
+        for candidate in candidates {
+            match self.loader.load(&candidate) {
+                Ok(text) => return Ok((candidate, text)),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()).at_position(pos, || format!("failed to read included file: {}", candidate.display())),
+            }
+        }
+        Err(error_at!(pos, "included file not found: \"{included}\""))
+    }
+
+    fn cycle_error(&self, included: &str, pos: &Position) -> Error {
+        self.chain.iter()
+                  .rev()
+                  .filter_map(|(_, site)| site.clone())
+                  .fold(SourceError::new(pos.clone(), format!("include cycle detected: \"{included}\"")),
+                        |err, site| err.with_label(site, "included from here"))
+                  .into()
+    }
+}
+
+/// Tokenizes file and recursively replaces `%include "path"` directives with tokens of included files.
+/// Paths are resolved relative to the including file, then to each of `include_dirs`.
+pub fn tokenize(text: &str, filename: &str, include_dirs: &[PathBuf], loader: &dyn SourceLoader) -> Result<Vec<Token>> {
+    let path = Path::new(filename);
+    let mut includer = Includer{include_dirs, loader, chain: vec![(normalize(path), None)]};
+    includer.tokenize(text, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    impl SourceLoader for HashMap<PathBuf, String> {
+        fn load(&self, path: &Path) -> io::Result<String> {
+            self.get(path).cloned().ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+    }
+
+    fn files(files: &[(&str, &str)]) -> HashMap<PathBuf, String> {
+        files.iter()
+             .map(|(path, text)| (PathBuf::from(path), text.to_string()))
+             .collect()
+    }
+
+    fn idents(tokens: &[Token]) -> Vec<String> {
+        tokens.iter()
+              .map(|x| match x {
+                  Token::Ident(i, pos) => format!("{i}@{pos}"),
+                  _ => panic!("unexpected token {x:?}"),
+              })
+              .collect()
+    }
+
+    #[test]
+    fn includes_file_relative_to_including_file() {
+        let loader = files(&[("src/lib/io.asm", "IN OUT"), ("src/lib/util.asm", "%include \"io.asm\" DUP")]);
+
+        let got = tokenize("%include \"lib/util.asm\" HALT", "src/main.asm", &[], &loader);
+
+        assert_eq!(idents(&got.unwrap()), vec![
+            "IN@src/lib/io.asm:1:1",
+            "OUT@src/lib/io.asm:1:4",
+            "DUP@src/lib/util.asm:1:19",
+            "HALT@src/main.asm:1:25",
+        ]);
+    }
+
+    #[test]
+    fn includes_file_from_include_dirs() {
+        let loader = files(&[("first/a.asm", "A"), ("second/b.asm", "B")]);
+        let include_dirs = [PathBuf::from("first"), PathBuf::from("second")];
+
+        let got = tokenize("%include \"b.asm\" %include \"a.asm\"", "main.asm", &include_dirs, &loader);
+
+        assert_eq!(idents(&got.unwrap()), vec!["B@second/b.asm:1:1", "A@first/a.asm:1:1"]);
+    }
+
+    #[test]
+    fn error_on_missing_file() {
+        let got = tokenize("1\n  %include \"nope.asm\"", "main.asm", &[], &files(&[]));
+
+        assert_eq!(got.unwrap_err().to_string(), "main.asm:2:3: included file not found: \"nope.asm\"");
+    }
+
+    #[test]
+    fn error_on_include_cycle() {
+        let loader = files(&[("a.asm", "%include \"lib/b.asm\""), ("lib/b.asm", "DUP %include \"../a.asm\"")]);
+
+        let got = tokenize("%include \"a.asm\"", "main.asm", &[], &loader);
+
+        assert_eq!(got.unwrap_err().to_string(), "lib/b.asm:1:5: include cycle detected: \"../a.asm\"
+    included from here: a.asm:1:1
+    included from here: main.asm:1:1");
+    }
+
+    #[test]
+    fn error_on_self_include() {
+        let got = tokenize("%include \"./main.asm\"", "main.asm", &[], &files(&[("main.asm", "")]));
+
+        assert_eq!(got.unwrap_err().to_string(), "main.asm:1:1: include cycle detected: \"./main.asm\"");
+    }
+}
@@ -1,13 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use anyhow::{anyhow, Result};
-use beau_collector::BeauCollector;
+use anyhow::Result;
 
 use super::command::COMMANDS;
-use crate::models::token::Token;
+use super::visibility::source_name;
+use crate::models::error::{CollectErrors as _, SourceError, error_at, bail_at};
+use crate::models::token::{Position, Token};
 use crate::models::command::Opcode;
 
-fn get_default_labels() -> HashMap<&'static str, Opcode> {
+pub fn get_default_labels() -> HashMap<&'static str, Opcode> {
     COMMANDS.iter()
             .enumerate()
             .filter_map(
@@ -22,43 +23,131 @@ fn get_default_labels() -> HashMap<&'static str, Opcode> {
             .collect()
 }
 
-pub fn get_labels<'a>(tokens: &'a [Token]) -> Result<HashMap<&'a str, Opcode>> {
+pub fn is_builtin(name: &str) -> bool {
+    name == "PROGRAM_SIZE" || COMMANDS.iter().flatten().any(|cmd| cmd.mnemonics.contains(&name))
+}
+
+/// Replaces `NAME EQU value` sequences with constant definitions.
+pub fn fold_constants(tokens: Vec<Token>) -> Result<Vec<Token>> {
+    let mut res: Vec<Token> = vec![];
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Ident(equ, pos) if equ == "EQU" => {
+                let (name, name_pos) = match res.pop() {
+                    Some(Token::Ident(name, pos)) => (name, pos),
+                    _ => bail_at!(pos, "expected constant name before EQU"),
+                };
+                match tokens.next() {
+                    Some(Token::Integer(value, _)) => res.push(Token::Constant(name, value, name_pos)),
+                    _ => bail_at!(pos, "expected integer value after EQU"),
+                }
+            },
+            _ => res.push(token),
+        }
+    }
+    Ok(res)
+}
+
+fn resolve_local_label(name: &str, scope: Option<&str>, declared: &HashSet<String>, pos: &Position) -> Result<String> {
+    let global = scope.ok_or_else(|| error_at!(pos, "local label {name} used outside of global label scope"))?;
+    let qualified = format!("{global}{name}");
+    if !declared.contains(&qualified) {
+        bail_at!(pos, "local label {name} is not declared in scope of {global}")
+    }
+    Ok(qualified)
+}
+
+/// Renames `.local` labels to `Global.local`, where `Global` is the nearest preceding global label.
+pub fn resolve_local_labels(tokens: Vec<Token>) -> Result<Vec<Token>> {
+    let mut declared = HashSet::new();
+    let mut scope = None;
+    for token in &tokens {
+        match token {
+            Token::Declaration(decl, _) if decl.starts_with('.') => if let Some(global) = scope {
+                declared.insert(format!("{global}{decl}"));
+            },
+            Token::Declaration(decl, _) => scope = Some(decl.as_str()),
+            _ => {},
+        }
+    }
+
+    let mut scope: Option<String> = None;
+    tokens.into_iter()
+          .map(|mut token| -> Result<Token> {
+              match &mut token {
+                  Token::Declaration(decl, pos) if decl.starts_with('.') => {
+                      let global = scope.as_ref()
+                                        .ok_or_else(|| error_at!(pos, "local label {decl} declared outside of global label scope"))?;
+                      *decl = format!("{global}{decl}");
+                  },
+                  Token::Declaration(decl, _) => scope = Some(decl.clone()),
+                  Token::Ident(name, pos) if name.starts_with('.') =>
+                      *name = resolve_local_label(name, scope.as_deref(), &declared, pos)?,
+                  Token::Expression(expression, pos) => for name in expression.symbols_mut() {
+                      if name.starts_with('.') {
+                          *name = resolve_local_label(name, scope.as_deref(), &declared, pos)?;
+                      }
+                  },
+                  _ => {},
+              }
+              Ok(token)
+          })
+          .collect_errors()
+}
+
+fn declared_twice(pos: &Position, message: String, first: Option<&&Position>) -> anyhow::Error {
+    let err = SourceError::new(pos.clone(), message);
+    match first {
+        Some(first) => err.with_label((*first).clone(), "first declared here").into(),
+        None => err.into(),
+    }
+}
+
+pub fn get_labels(tokens: &[Token]) -> Result<HashMap<&str, Opcode>> {
     let mut current = 256;
     let mut labels = get_default_labels();
+    let mut declared: HashMap<&str, &Position> = HashMap::new();
     let mut errors: Vec<Result<()>> = vec![];
     for token in tokens {
-        if let Token::Declaration(decl, pos) = token {
-            if let Some(_) = labels.insert(decl, current) {
-                errors.push(Err(anyhow!("{pos}: label declared twice: {decl}")));
-            }
-        } else {
-            current += 1;
+        match token {
+            Token::Declaration(decl, pos) => if labels.insert(decl, current).is_some() {
+                errors.push(Err(declared_twice(pos, format!("label declared twice: {}", source_name(decl)), declared.get(decl.as_str()))));
+            } else {
+                declared.insert(decl, pos);
+            },
+            Token::Constant(name, value, pos) => if labels.insert(name, *value).is_some() {
+                errors.push(Err(declared_twice(pos, format!("constant defined twice: {}", source_name(name)), declared.get(name.as_str()))));
+            } else {
+                declared.insert(name, pos);
+            },
+            _ => current += 1,
         }
     }
     labels.insert("PROGRAM_SIZE", current);
-    errors.into_iter().bcollect()?;
+    errors.into_iter().collect_errors::<()>()?;
     Ok(labels)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::token::Position;
+    use crate::logic::tokenize::tokenize;
 
     #[test]
     fn calculates_labels_map() {
         let tokens = vec![
-            Token::Integer(-40, Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Ident("_Loop".to_string(), Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Declaration("a1".to_string(), Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Ident("HALT".to_string(), Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Ident("_Read_number_".to_string(), Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Ident("a1".to_string(), Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Integer(123, Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Integer(1234, Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Ident("PROGRAM_SIZE".to_string(), Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Declaration("_Loop".to_string(), Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Declaration("_Read_number_".to_string(), Position{filename: "test".to_string(), line: 1, column: 1}),
+            Token::Integer(-40, Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("_Loop".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Declaration("a1".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("HALT".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("_Read_number_".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("a1".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Integer(123, Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Integer(1234, Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("PROGRAM_SIZE".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Declaration("_Loop".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Declaration("_Read_number_".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
         ];
         let mut expected = get_default_labels();
         expected.insert("_Loop", 264);
@@ -74,13 +163,108 @@ mod tests {
     #[test]
     fn error_on_duplicate_declaration() {
         let tokens = vec![
-            Token::Declaration("a1".to_string(), Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Integer(123, Position{filename: "test".to_string(), line: 1, column: 2}),
-            Token::Declaration("a1".to_string(), Position{filename: "test".to_string(), line: 1, column: 3}),
+            Token::Declaration("a1".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Integer(123, Position{filename: "test".to_string(), line: 1, column: 2, expansion: None}),
+            Token::Declaration("a1".to_string(), Position{filename: "test".to_string(), line: 1, column: 3, expansion: None}),
         ];
 
         let got = get_labels(&tokens);
 
-        assert_eq!(got.unwrap_err().to_string(), "test:1:3: label declared twice: a1");
+        assert_eq!(got.unwrap_err().to_string(), "test:1:3: label declared twice: a1
+    first declared here: test:1:1");
+    }
+
+    #[test]
+    fn constants_do_not_take_space() {
+        let tokens = vec![
+            Token::Declaration("a1".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Constant("Size".to_string(), 2000, Position{filename: "test".to_string(), line: 1, column: 2, expansion: None}),
+            Token::Integer(123, Position{filename: "test".to_string(), line: 1, column: 3, expansion: None}),
+            Token::Declaration("a2".to_string(), Position{filename: "test".to_string(), line: 1, column: 4, expansion: None}),
+        ];
+        let mut expected = get_default_labels();
+        expected.insert("a1", 256);
+        expected.insert("Size", 2000);
+        expected.insert("a2", 257);
+        expected.insert("PROGRAM_SIZE", 257);
+
+        let got = get_labels(&tokens);
+
+        assert_eq!(got.unwrap(), expected)
+    }
+
+    #[test]
+    fn error_on_constant_redefinition() {
+        let tokens = vec![
+            Token::Constant("Size".to_string(), 1, Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Constant("Size".to_string(), 2, Position{filename: "test".to_string(), line: 2, column: 1, expansion: None}),
+            Token::Declaration("Size".to_string(), Position{filename: "test".to_string(), line: 3, column: 1, expansion: None}),
+        ];
+
+        let got = get_labels(&tokens);
+
+        assert_eq!(got.unwrap_err().to_string(), "test:2:1: constant defined twice: Size
+    first declared here: test:1:1
+test:3:1: label declared twice: Size
+    first declared here: test:1:1");
+    }
+
+    #[test]
+    fn resolves_local_labels_in_scope_of_global_label() {
+        let tokens = tokenize(":First .end JMP :.end :Second .end+1 :.end", "test").unwrap();
+
+        let got = resolve_local_labels(tokens).unwrap()
+                                              .into_iter()
+                                              .map(|x| match x {
+                                                  Token::Declaration(i, _) => format!(":{i}"),
+                                                  Token::Ident(i, _) => i,
+                                                  Token::Expression(i, _) => i.to_string(),
+                                                  _ => panic!("unexpected token {x:?}"),
+                                              })
+                                              .collect::<Vec<_>>();
+
+        assert_eq!(got, vec![":First", "First.end", "JMP", ":First.end", ":Second", "(Second.end+1)", ":Second.end"]);
+    }
+
+    #[test]
+    fn error_on_local_label_out_of_scope() {
+        let tokens = tokenize(".end :.start :First .start :Second .end", "test").unwrap();
+
+        let got = resolve_local_labels(tokens);
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:1: local label .end used outside of global label scope
+test:1:6: local label .start declared outside of global label scope
+test:1:21: local label .start is not declared in scope of First
+test:1:36: local label .end is not declared in scope of Second");
+    }
+
+    #[test]
+    fn folds_constant_definitions() {
+        let tokens = vec![
+            Token::Ident("Size".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("EQU".to_string(), Position{filename: "test".to_string(), line: 1, column: 6, expansion: None}),
+            Token::Integer(10, Position{filename: "test".to_string(), line: 1, column: 10, expansion: None}),
+            Token::Ident("Size".to_string(), Position{filename: "test".to_string(), line: 2, column: 1, expansion: None}),
+        ];
+
+        let got = fold_constants(tokens);
+
+        assert_eq!(got.unwrap(), vec![
+            Token::Constant("Size".to_string(), 10, Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("Size".to_string(), Position{filename: "test".to_string(), line: 2, column: 1, expansion: None}),
+        ]);
+    }
+
+    #[test]
+    fn error_on_constant_without_value() {
+        let tokens = vec![
+            Token::Ident("Size".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("EQU".to_string(), Position{filename: "test".to_string(), line: 1, column: 6, expansion: None}),
+            Token::Ident("ADD".to_string(), Position{filename: "test".to_string(), line: 1, column: 10, expansion: None}),
+        ];
+
+        let got = fold_constants(tokens);
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:6: expected integer value after EQU");
     }
 }
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::models::error::{CollectErrors as _, error_at, SourceError};
+use crate::models::command::{Instruction, Opcode};
+use crate::models::lint::{Level, Lint, Warning};
+use crate::models::token::{Position, Token};
+use super::cfg::{self, Flow};
+use super::visibility::source_name;
+use super::command::{get_command, is_command, COMMANDS};
+
+pub const LINTS: [Lint; 6] = [
+    Lint{name: "unused_label", description: "label is never referenced", default: Level::Warn},
+    Lint{name: "unreachable_code", description: "code can't be reached from program start", default: Level::Warn},
+    Lint{name: "fall_through_into_data", description: "execution continues into label pointing at data", default: Level::Warn},
+    Lint{name: "negative_literal", description: "negative integer literal is executed as command opcode", default: Level::Warn},
+    Lint{name: "mnemonic_case", description: "label differs from mnemonic only in case", default: Level::Warn},
+    Lint{name: "missing_halt", description: "program has no HALT instruction", default: Level::Warn},
+];
+
+/// Describes available lints, one per line.
+pub fn describe() -> String {
+    LINTS.iter()
+         .map(|x| format!("{:<24} {:<6} {}\n", x.name, format!("{:?}", x.default).to_lowercase(), x.description))
+         .collect()
+}
+
+pub type Pragma = (String, Level, Position);
+
+fn parse_level(name: &str) -> Option<Level> {
+    match name {
+        "allow" => Some(Level::Allow),
+        "warn" => Some(Level::Warn),
+        "deny" => Some(Level::Deny),
+        _ => None,
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if !LINTS.iter().any(|x| x.name == name) {
+        bail!("unknown lint: {name}")
+    }
+    Ok(())
+}
+
+/// Removes `%allow`/`%warn`/`%deny` pragmas, collecting lint names listed on the same line.
+pub fn collect_pragmas(tokens: Vec<Token>) -> Result<(Vec<Token>, Vec<Pragma>)> {
+    let mut pragmas = vec![];
+    let mut res = vec![];
+    let mut errors: Vec<Result<()>> = vec![];
+    let mut pragma: Option<(String, Level, Position)> = None;
+    for token in tokens {
+        match (&pragma, token) {
+            (_, Token::Directive(name, pos)) if parse_level(&name).is_some() => pragma = Some((name.clone(), parse_level(&name).unwrap(), pos)),
+            (Some((_, level, pos)), Token::Ident(lint, lint_pos)) if lint_pos.same_line(pos) => {
+                match check_name(&lint) {
+                    Ok(()) => pragmas.push((lint, *level, lint_pos)),
+                    Err(err) => errors.push(Err(error_at!(lint_pos, "{err}"))),
+                }
+            },
+            (Some((name, _, pos)), token) if token.position().same_line(pos) => {
+                errors.push(Err(error_at!(token.position(), "expected lint name after %{name}")));
+            },
+            (_, token) => {
+                pragma = None;
+                res.push(token);
+            },
+        }
+    }
+    errors.into_iter().collect_errors::<()>()?;
+    Ok((res, pragmas))
+}
+
+/// Parses lint level given in command line, e.g. `--deny` and `unused_label`.
+pub fn parse_option(flag: &str, name: &str) -> Result<(String, Level)> {
+    let level = flag.strip_prefix("--").and_then(parse_level)
+                    .ok_or_else(|| anyhow!("unknown lint level option: {flag}"))?;
+    check_name(name)?;
+    Ok((name.to_string(), level))
+}
+
+/// Warns about negative literals which can be executed, ones in data are fine.
+fn negative_literals(code: &[Instruction], flow: &Flow) -> Vec<Warning> {
+    code.iter()
+        .enumerate()
+        .filter(|(i, _)| flow.is_reachable(*i))
+        .filter_map(|(_, x)| match &x.token {
+            Token::Integer(i, pos) if *i < 0 => Some(Warning{
+                lint: "negative_literal",
+                position: pos.clone(),
+                message: match get_command(*i) {
+                    Some(cmd) => format!("negative literal {i} is executed as {}", cmd.mnemonics[0]),
+                    None => format!("negative literal {i} is not a valid opcode"),
+                },
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn mnemonic_case(tokens: &[Token]) -> Vec<Warning> {
+    tokens.iter()
+          .filter_map(|x| match x {
+              Token::Declaration(name, pos) => {
+                  let name = source_name(name);
+                  let mnemonic = COMMANDS.iter()
+                                         .flatten()
+                                         .flat_map(|x| x.mnemonics.iter())
+                                         .find(|x| x.eq_ignore_ascii_case(name) && **x != name)?;
+                  Some(Warning{
+                      lint: "mnemonic_case",
+                      position: pos.clone(),
+                      message: format!("label {name} is spelled like mnemonic {mnemonic}"),
+                  })
+              },
+              _ => None,
+          })
+          .collect()
+}
+
+fn missing_halt(code: &[Instruction]) -> Vec<Warning> {
+    match code.last() {
+        Some(last) if !code.iter().any(|x| is_command(x.opcode, "HALT")) => vec![Warning{
+            lint: "missing_halt",
+            position: last.token.position().clone(),
+            message: "program has no HALT instruction".to_string(),
+        }],
+        _ => vec![],
+    }
+}
+
+/// Runs every lint over assembled program.
+pub fn check(tokens: &[Token], code: &[Instruction], labels: &HashMap<&str, Opcode>) -> Vec<Warning> {
+    let flow = cfg::analyze(tokens, code, labels);
+    let mut res = flow.warnings();
+    res.extend(negative_literals(code, &flow));
+    res.extend(mnemonic_case(tokens));
+    res.extend(missing_halt(code));
+    res
+}
+
+/// Drops allowed warnings and fails if any denied one is left.
+/// Levels from `options` take precedence over pragmas, which override lint defaults.
+/// Pragmas apply only to warnings in the file they are written in.
+pub fn apply_levels(warnings: Vec<Warning>, pragmas: &[Pragma], options: &[(String, Level)]) -> Result<Vec<Warning>> {
+    let level = |warning: &Warning| {
+        let lint = warning.lint;
+        options.iter().rev().find(|(name, _)| name == lint).map(|(_, level)| *level)
+               .or_else(|| pragmas.iter()
+                                  .rev()
+                                  .find(|(name, _, pos)| name == lint && pos.filename == warning.position.filename)
+                                  .map(|(_, level, _)| *level))
+               .unwrap_or_else(|| LINTS.iter().find(|x| x.name == lint).unwrap().default)
+    };
+    let mut res = vec![];
+    let mut errors: Vec<Result<()>> = vec![];
+    for warning in warnings {
+        match level(&warning) {
+            Level::Allow => {},
+            Level::Warn => res.push(warning),
+            Level::Deny => errors.push(Err(SourceError::new(warning.position, warning.message).with_lint(warning.lint).into())),
+        }
+    }
+    errors.into_iter().collect_errors::<()>()?;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logic::assembly::{Options, Program, TextFile};
+    use super::*;
+
+    fn lint(text: &str, options: &Options) -> Result<Vec<String>> {
+        let files = [TextFile{name: "test".to_owned(), text: text.to_owned()}];
+        Ok(Program::preprocess(&files, options)?.warnings(options)?.iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn warns_about_suspicious_code() {
+        let got = lint("Add CALL -40\n:Add -100 RET", &Options::default());
+
+        assert_eq!(got.unwrap(), vec![
+            "test:1:10: warning[negative_literal]: negative literal -40 is executed as MUL",
+            "test:2:6: warning[negative_literal]: negative literal -100 is not a valid opcode",
+            "test:2:1: warning[mnemonic_case]: label Add is spelled like mnemonic ADD",
+            "test:2:11: warning[missing_halt]: program has no HALT instruction",
+        ]);
+    }
+
+    #[test]
+    fn applies_levels_from_pragmas_and_options() {
+        let text = "%allow missing_halt\n%deny negative_literal unused_label\n-40 :Unused";
+        let options = Options{lint_levels: vec![("unused_label".to_string(), Level::Warn)], ..Default::default()};
+
+        let got = lint(text, &options);
+
+        assert_eq!(got.unwrap_err().to_string(), "test:3:1: error[negative_literal]: negative literal -40 is executed as MUL");
+        assert_eq!(lint("%allow negative_literal missing_halt\n-40 :Unused", &options).unwrap(), vec![
+            "test:2:5: warning[unused_label]: label is never referenced: Unused",
+        ]);
+    }
+
+    #[test]
+    fn applies_pragmas_to_own_file_only() {
+        let files = [
+            TextFile{name: "main".to_owned(), text: "%extern Print\n%allow unused_label\nPrint CALL 0 HALT :Unused".to_owned()},
+            TextFile{name: "lib".to_owned(), text: "%global Print\n:Print RET :Unused".to_owned()},
+        ];
+
+        let got = Program::preprocess(&files, &Options::default()).unwrap().warnings(&Options::default()).unwrap();
+
+        assert_eq!(got.iter().map(ToString::to_string).collect::<Vec<_>>(), vec![
+            "lib:2:12: warning[unused_label]: label is never referenced: Unused",
+        ]);
+    }
+
+    #[test]
+    fn ignores_negative_literals_in_data() {
+        let got = lint("Table LOAD OUT 0 HALT\n:Table 72 -1 -100", &Options::default());
+
+        assert_eq!(got.unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn error_on_unknown_lint() {
+        let got = lint("%deny unused 1\n0 HALT", &Options::default());
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:7: unknown lint: unused
+test:1:14: expected lint name after %deny");
+        assert_eq!(parse_option("--allow", "missing").unwrap_err().to_string(), "unknown lint: missing");
+    }
+}
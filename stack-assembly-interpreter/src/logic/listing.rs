@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::models::command::Instruction;
+use crate::models::token::Token;
+use super::visibility::{source_expression, source_name};
+
+fn symbol_name(token: &Token) -> String {
+    match token {
+        Token::Ident(i, _) => source_name(i).to_string(),
+        Token::Integer(i, _) => i.to_string(),
+        Token::Expression(i, _) => source_expression(i),
+        _ => String::new(),
+    }
+}
+
+fn push_labels(res: &mut String, labels: &mut Vec<&str>, address: i64) {
+    for label in labels.drain(..) {
+        res.push_str(&format!("{label:<24} {address:>7}\n"));
+    }
+}
+
+/// Renders one line per instruction: labels declared at it, address, opcode, symbol and source line.
+/// Source line is shown only for first instruction of each line, `sources` maps file names to their text.
+pub fn listing(tokens: &[Token], instructions: &[Instruction], sources: &HashMap<String, String>) -> String {
+    let mut res = format!("{:<24} {:>7} {:>8}  {:<24} SOURCE\n", "LABEL", "ADDRESS", "OPCODE", "SYMBOL");
+    let mut instructions = instructions.iter();
+    let mut address = 256;
+    let mut labels = vec![];
+    let mut last_line = None;
+    for token in tokens {
+        let instruction = match token {
+            Token::Declaration(name, _) => {
+                labels.push(source_name(name));
+                continue
+            },
+            Token::Constant(_, _, _) => continue,
+            _ => instructions.next().unwrap(),
+        };
+        let gutter = labels.pop().unwrap_or_default();
+        push_labels(&mut res, &mut labels, address);
+
+        let pos = instruction.token.position();
+        let source = match last_line.replace(pos) {
+            Some(last) if last.same_line(pos) => String::new(),
+            _ => {
+                let text = sources.get(&pos.filename)
+                                  .and_then(|text| text.lines().nth(pos.line.saturating_sub(1)))
+                                  .unwrap_or_default();
+                format!("{}:{}  {}", pos.filename, pos.line, text.trim())
+            },
+        };
+        let line = format!("{gutter:<24} {address:>7} {:>8}  {:<24} {source}", instruction.opcode, symbol_name(&instruction.token));
+        res.push_str(line.trim_end());
+        res.push('\n');
+        address += 1;
+    }
+    push_labels(&mut res, &mut labels, address);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logic::assembly::{Options, Program, TextFile};
+
+    #[test]
+    fn lists_instructions_with_labels_and_source() {
+        let files = [TextFile{name: "main".to_owned(), text: String::from("Size EQU 2\n:Main :Start Start JMP\n  'a' (Size+1) ; c\n:End")}];
+
+        let got = Program::preprocess(&files, &Options::default()).unwrap().listing().unwrap();
+
+        assert_eq!(got, "\
+LABEL                    ADDRESS   OPCODE  SYMBOL                   SOURCE
+Main                         256
+Start                        256      256  Start                    main:2  :Main :Start Start JMP
+                             257      -13  JMP
+                             258       97  97                       main:3  'a' (Size+1) ; c
+                             259        3  (Size+1)
+End                          260
+");
+    }
+}
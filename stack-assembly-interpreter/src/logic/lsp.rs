@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, References, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, Location, MarkedString, OneOf, PublishDiagnosticsParams,
+    Range, ReferenceParams, ServerCapabilities, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+
+use crate::models::token::Position;
+use super::analysis::Analysis;
+use super::assembly::{Options, TextFile};
+
+fn filename(uri: &Url) -> String {
+    uri.to_file_path()
+       .map(|path| path.display().to_string())
+       .unwrap_or_else(|_| uri.to_string())
+}
+
+fn uri(filename: &str) -> Option<Url> {
+    Url::from_file_path(filename).or_else(|_| Url::parse(filename)).ok()
+}
+
+/// Converts 1-based column counted in chars of `line` to 0-based offset in UTF-16 code units, as LSP counts them.
+fn utf16_offset(line: &str, column: usize) -> u32 {
+    let column = column.saturating_sub(1);
+    let units: usize = line.chars().take(column).map(char::len_utf16).sum();
+    (units + column.saturating_sub(line.chars().count())) as u32
+}
+
+/// Converts 0-based offset in UTF-16 code units of `line` to 1-based column counted in chars.
+fn char_column(line: &str, offset: u32) -> usize {
+    let mut units = 0;
+    line.chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= offset as usize
+        })
+        .count() + 1
+}
+
+/// Open documents, all of them are considered to be files of single program.
+struct Server {
+    connection: Connection,
+    documents: BTreeMap<Url, String>,
+    analysis: Analysis,
+}
+
+impl Server {
+    /// Returns text of line of open document, empty if document is not open.
+    fn line(&self, filename: &str, line: usize) -> &str {
+        self.documents.iter()
+                      .find(|(uri, _)| self::filename(uri) == filename)
+                      .and_then(|(_, text)| text.lines().nth(line.checked_sub(1)?))
+                      .unwrap_or_default()
+    }
+
+    fn range(&self, pos: &Position, length: usize) -> Range {
+        let line = self.line(&pos.filename, pos.line);
+        let start = lsp_types::Position{line: pos.line.saturating_sub(1) as u32, character: utf16_offset(line, pos.column)};
+        let end = lsp_types::Position{character: utf16_offset(line, pos.column + length), ..start};
+        Range{start, end}
+    }
+
+    fn location(&self, pos: &Position) -> Option<Location> {
+        Some(Location{uri: uri(&pos.filename)?, range: self.range(pos, 0)})
+    }
+
+    fn position(&self, params: &TextDocumentPositionParams) -> Position {
+        let filename = filename(&params.text_document.uri);
+        let line = params.position.line as usize + 1;
+        let column = char_column(self.line(&filename, line), params.position.character);
+        Position{filename, line, column, expansion: None}
+    }
+
+    fn update(&mut self) -> Result<()> {
+        let files = self.documents.iter()
+                                  .map(|(uri, text)| TextFile{name: filename(uri), text: text.clone()})
+                                  .collect();
+        self.analysis = Analysis::new(files, &Options::default());
+
+        let mut diagnostics: BTreeMap<Url, Vec<lsp_types::Diagnostic>> = self.documents.keys()
+                                                                                       .map(|uri| (uri.clone(), vec![]))
+                                                                                       .collect();
+        for diagnostic in &self.analysis.diagnostics {
+            let Some(uri) = uri(&diagnostic.position.filename) else {
+                continue
+            };
+            diagnostics.entry(uri).or_default().push(lsp_types::Diagnostic{
+                range: self.range(&diagnostic.position, diagnostic.length),
+                severity: Some(DiagnosticSeverity::ERROR),
+                message: diagnostic.message.clone(),
+                ..Default::default()
+            });
+        }
+        for (uri, diagnostics) in diagnostics {
+            let params = PublishDiagnosticsParams{uri, diagnostics, version: None};
+            self.connection.sender.send(Notification::new(PublishDiagnostics::METHOD.to_string(), params).into())?;
+        }
+        Ok(())
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.documents.insert(params.text_document.uri, params.text_document.text);
+            },
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(params.text_document.uri, change.text);
+                }
+            },
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+            },
+            _ => return Ok(()),
+        }
+        self.update()
+    }
+
+    fn handle_request(&self, request: Request) -> Result<Response> {
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+                let res = self.analysis.definition(&self.position(&params.text_document_position_params))
+                                       .as_ref()
+                                       .and_then(|pos| self.location(pos))
+                                       .map(GotoDefinitionResponse::Scalar);
+                serde_json::to_value(res)?
+            },
+            References::METHOD => {
+                let params: ReferenceParams = serde_json::from_value(request.params)?;
+                let res = self.analysis.references(&self.position(&params.text_document_position))
+                                       .iter()
+                                       .filter_map(|pos| self.location(pos))
+                                       .collect::<Vec<_>>();
+                serde_json::to_value(res)?
+            },
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(request.params)?;
+                let res = self.analysis.hover(&self.position(&params.text_document_position_params))
+                                       .map(|text| Hover{contents: HoverContents::Scalar(MarkedString::String(text)), range: None});
+                serde_json::to_value(res)?
+            },
+            Completion::METHOD => {
+                let res = Analysis::completions().into_iter()
+                                                 .map(|(mnemonic, effect)| CompletionItem{
+                                                     label: mnemonic.to_string(),
+                                                     kind: Some(CompletionItemKind::KEYWORD),
+                                                     detail: Some(format!("( {effect} )")),
+                                                     ..Default::default()
+                                                 })
+                                                 .collect::<Vec<_>>();
+                serde_json::to_value(res)?
+            },
+            method => return Ok(Response::new_err(request.id, lsp_server::ErrorCode::MethodNotFound as i32, format!("unknown method: {method}"))),
+        };
+        Ok(Response{id: request.id, result: Some(result), error: None})
+    }
+
+    fn run(&mut self) -> Result<()> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(())
+                    }
+                    let id = request.id.clone();
+                    let response = self.handle_request(request)
+                                       .unwrap_or_else(|err| Response::new_err(id, lsp_server::ErrorCode::InvalidParams as i32, err.to_string()));
+                    self.connection.sender.send(response.into())?;
+                },
+                Message::Notification(notification) => {
+                    let method = notification.method.clone();
+                    if let Err(err) = self.handle_notification(notification) {
+                        eprintln!("failed to handle notification {method}: {err:#}");
+                    }
+                },
+                Message::Response(_) => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs language server over stdin/stdout until client requests shutdown.
+pub fn serve() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities{
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server{connection, documents: BTreeMap::new(), analysis: Analysis::new(vec![], &Options::default())};
+    server.run()?;
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn converts_columns_to_utf16_offsets_and_back() {
+        let line = "'é' '😀' X";
+
+        assert_eq!([6, 9, 11].map(|column| utf16_offset(line, column)), [5, 9, 11]);
+        assert_eq!([5, 6, 9].map(|offset| char_column(line, offset)), [6, 6, 9]);
+    }
+
+    #[test]
+    fn keeps_serving_after_malformed_notification() {
+        let (connection, client) = Connection::memory();
+        let server = std::thread::spawn(move || {
+            Server{connection, documents: BTreeMap::new(), analysis: Analysis::new(vec![], &Options::default())}.run()
+        });
+
+        client.sender.send(Notification::new(DidChangeTextDocument::METHOD.to_string(), json!({"textDocument": 1})).into()).unwrap();
+        client.sender.send(Request::new(1.into(), Completion::METHOD.to_string(), json!({})).into()).unwrap();
+        let Ok(Message::Response(response)) = client.receiver.recv() else {
+            panic!("expected response to completion request")
+        };
+        assert_eq!(response.id, 1.into());
+        assert!(response.result.is_some_and(|x| x.as_array().is_some_and(|items| !items.is_empty())));
+
+        client.sender.send(Request::new(2.into(), "shutdown".to_string(), json!(null)).into()).unwrap();
+        assert!(matches!(client.receiver.recv(), Ok(Message::Response(_))));
+        client.sender.send(Notification::new("exit".to_string(), json!(null)).into()).unwrap();
+        assert!(server.join().unwrap().is_ok());
+    }
+}
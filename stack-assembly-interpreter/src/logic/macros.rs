@@ -0,0 +1,324 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+
+use crate::models::error::{CollectErrors as _, error_at, bail_at};
+use crate::models::token::{Position, Token};
+
+const MAX_EXPANSION_DEPTH: usize = 64;
+/// Limit on number of tokens produced by expansion of single invocation or `%rep` block.
+const MAX_EXPANSION_SIZE: usize = 1 << 20;
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+    /// Labels declared in body, renamed in each invocation so that it can be used more than once.
+    labels: HashSet<String>,
+}
+
+impl Macro {
+    /// Generated names contain `#`, so they can't clash with ones of the code.
+    fn instantiate(&self, args: &[Token], site: &Position, invocation: usize) -> Vec<Token> {
+        let rename = |name: &str| match self.labels.contains(name) {
+            true => format!("{name}#{invocation}"),
+            false => name.to_string(),
+        };
+        self.body.iter()
+                 .map(|token| match token {
+                     Token::Ident(name, _) if self.params.contains(name) => {
+                         let i = self.params.iter().position(|param| param == name).unwrap();
+                         args[i].clone()
+                     },
+                     Token::Declaration(name, pos) => Token::Declaration(rename(name), site.expanded(pos)),
+                     Token::Ident(name, pos) => Token::Ident(rename(name), site.expanded(pos)),
+                     Token::Expression(expression, pos) => {
+                         let mut expression = expression.clone();
+                         for name in expression.symbols_mut() {
+                             *name = rename(name);
+                         }
+                         Token::Expression(expression, site.expanded(pos))
+                     },
+                     _ => token.clone().with_position(site.expanded(token.position())),
+                 })
+                 .collect()
+    }
+}
+
+fn is_directive(token: &Token, name: &str) -> bool {
+    matches!(token, Token::Directive(directive, _) if directive == name)
+}
+
+/// Splits `tokens` into body of block opened at `pos` and tokens following its closing directive.
+fn take_block<'a>(tokens: &'a [Token], open: &str, close: &str, pos: &Position) -> Result<(&'a [Token], &'a [Token])> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if is_directive(token, open) {
+            depth += 1;
+        } else if is_directive(token, close) {
+            if depth == 0 {
+                return Ok((&tokens[..i], &tokens[i+1..]))
+            }
+            depth -= 1;
+        }
+    }
+    Err(error_at!(pos, "unterminated %{open}, expected %{close}"))
+}
+
+fn parse_definition(header: &[Token], body: &[Token], pos: &Position) -> Result<(String, Macro)> {
+    let (name, params) = header.split_first()
+                               .ok_or_else(|| error_at!(pos, "expected macro name after %macro"))?;
+    let name = match name {
+        Token::Ident(name, _) => name.clone(),
+        _ => bail_at!(name.position(), "expected macro name, got {name:?}"),
+    };
+    let params = params.iter()
+                       .map(|param| match param {
+                           Token::Ident(param, _) => Ok(param.clone()),
+                           _ => Err(error_at!(param.position(), "expected macro parameter name")),
+                       })
+                       .collect_errors::<Vec<_>>()?;
+    if let Some(nested) = body.iter().find(|x| is_directive(x, "macro")) {
+        bail_at!(nested.position(), "nested macro definitions are not allowed")
+    }
+    let labels = body.iter()
+                     .filter_map(|x| match x {
+                         Token::Declaration(name, _) => Some(name.clone()),
+                         _ => None,
+                     })
+                     .collect();
+    Ok((name, Macro{params, body: body.to_vec(), labels}))
+}
+
+fn collect_definitions(mut tokens: &[Token]) -> Result<(HashMap<String, Macro>, Vec<Token>)> {
+    let mut macros = HashMap::new();
+    let mut rest = vec![];
+    let mut errors: Vec<Result<()>> = vec![];
+    while let Some((token, tail)) = tokens.split_first() {
+        tokens = tail;
+        match token {
+            Token::Directive(directive, pos) if directive == "macro" => {
+                let header_len = tail.iter()
+                                     .take_while(|x| x.position().same_line(pos) && !is_directive(x, "endm"))
+                                     .count();
+                let (header, tail) = tail.split_at(header_len);
+                let (body, tail) = take_block(tail, "macro", "endm", pos)?;
+                tokens = tail;
+                match parse_definition(header, body, pos) {
+                    Ok((name, definition)) => if macros.insert(name.clone(), definition).is_some() {
+                        errors.push(Err(error_at!(pos, "macro defined twice: {name}")));
+                    },
+                    Err(err) => errors.push(Err(err)),
+                }
+            },
+            Token::Directive(directive, pos) if directive == "endm" =>
+                errors.push(Err(error_at!(pos, "%endm without matching %macro"))),
+            _ => rest.push(token.clone()),
+        }
+    }
+    errors.into_iter().collect_errors::<()>()?;
+    Ok((macros, rest))
+}
+
+fn expand_tokens(mut tokens: &[Token], macros: &HashMap<String, Macro>, depth: usize, invocations: &mut usize) -> Result<Vec<Token>> {
+    let mut res = vec![];
+    while let Some((token, tail)) = tokens.split_first() {
+        tokens = tail;
+        match token {
+            Token::Directive(directive, pos) if directive == "rep" => {
+                let count = match tail.first() {
+                    Some(Token::Integer(count, _)) if *count >= 0 => *count,
+                    _ => bail_at!(pos, "%rep expects non-negative repetition count"),
+                };
+                let (body, tail) = take_block(&tail[1..], "rep", "endrep", pos)?;
+                tokens = tail;
+                let body = expand_tokens(body, macros, depth, invocations)?;
+                if body.len().saturating_mul(count as usize) > MAX_EXPANSION_SIZE {
+                    bail_at!(pos, "%rep expands to more than {MAX_EXPANSION_SIZE} tokens")
+                }
+                for _ in 0..count {
+                    res.extend(body.iter().cloned());
+                }
+            },
+            Token::Directive(directive, pos) if directive == "endrep" =>
+                bail_at!(pos, "%endrep without matching %rep"),
+            Token::Ident(name, pos) if macros.contains_key(name) => {
+                if depth >= MAX_EXPANSION_DEPTH {
+                    bail_at!(pos, "macro expansion is too deep: {name}")
+                }
+                let definition = &macros[name];
+                if tail.len() < definition.params.len() {
+                    bail_at!(pos, "macro {name} expects {} arguments, got {}", definition.params.len(), tail.len())
+                }
+                let (args, tail) = tail.split_at(definition.params.len());
+                tokens = tail;
+                *invocations += 1;
+                let body = definition.instantiate(args, pos, *invocations);
+                res.extend(expand_tokens(&body, macros, depth+1, invocations)?);
+            },
+            _ => res.push(token.clone()),
+        }
+        if res.len() > MAX_EXPANSION_SIZE {
+            bail_at!(token.position(), "expansion produces more than {MAX_EXPANSION_SIZE} tokens")
+        }
+    }
+    Ok(res)
+}
+
+/// Expands `%macro NAME params... %endm` definitions and `%rep N ... %endrep` blocks.
+/// Macro parameters are the tokens on the same line as `%macro`.
+/// Labels declared in macro body are visible only inside its invocation.
+pub fn expand(tokens: &[Token]) -> Result<Vec<Token>> {
+    let (macros, rest) = collect_definitions(tokens)?;
+    expand_tokens(&rest, &macros, 0, &mut 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::tokenize::tokenize;
+
+    fn expand_text(text: &str) -> Result<Vec<Token>> {
+        expand(&tokenize(text, "test")?)
+    }
+
+    #[test]
+    fn expands_macro_with_parameters() {
+        let text = "%macro STORE addr value
+    addr value SAVE
+%endm
+STORE 2000 5 HALT";
+
+        let got = expand_text(text).unwrap();
+
+        assert_eq!(got, vec![
+            Token::Integer(2000, Position{filename: "test".to_string(), line: 4, column: 7, expansion: None}),
+            Token::Integer(5, Position{filename: "test".to_string(), line: 4, column: 12, expansion: None}),
+            Token::Ident("SAVE".to_string(), Position{filename: "test".to_string(), line: 4, column: 1, expansion: Some(Box::new(
+                Position{filename: "test".to_string(), line: 2, column: 16, expansion: None}
+            ))}),
+            Token::Ident("HALT".to_string(), Position{filename: "test".to_string(), line: 4, column: 14, expansion: None}),
+        ]);
+    }
+
+    #[test]
+    fn expands_nested_invocations() {
+        let text = "%macro LEAVE
+    GETFP RET
+%endm
+%macro TWICE
+    LEAVE LEAVE
+%endm
+TWICE";
+
+        let got = expand_text(text).unwrap()
+                                   .iter()
+                                   .map(|x| x.position().to_string())
+                                   .collect::<Vec<_>>();
+
+        assert_eq!(got, vec![
+            "test:7:1 (in macro body at test:5:5 (in macro body at test:2:5))",
+            "test:7:1 (in macro body at test:5:5 (in macro body at test:2:11))",
+            "test:7:1 (in macro body at test:5:11 (in macro body at test:2:5))",
+            "test:7:1 (in macro body at test:5:11 (in macro body at test:2:11))",
+        ]);
+    }
+
+    #[test]
+    fn expands_rep_blocks() {
+        let text = "%rep 2 1 %rep 3 DUP %endrep %endrep";
+
+        let got = expand_text(text).unwrap()
+                                   .into_iter()
+                                   .map(|x| match x {
+                                       Token::Integer(i, _) => i.to_string(),
+                                       Token::Ident(i, _) => i,
+                                       _ => panic!("unexpected token {x:?}"),
+                                   })
+                                   .collect::<Vec<_>>();
+
+        assert_eq!(got, vec!["1", "DUP", "DUP", "DUP", "1", "DUP", "DUP", "DUP"]);
+    }
+
+    #[test]
+    fn rep_count_from_macro_parameter() {
+        let text = "%macro ZEROS n
+%rep n 0 %endrep
+%endm
+ZEROS 3";
+
+        let got = expand_text(text).unwrap();
+
+        assert_eq!(got.len(), 3);
+    }
+
+    #[test]
+    fn renames_macro_labels_in_each_invocation() {
+        let text = "%macro Skip\n :Over Over JMP Over+1\n%endm\nSkip Skip Over";
+
+        let got = expand_text(text).unwrap()
+                                   .into_iter()
+                                   .map(|x| match x {
+                                       Token::Declaration(i, _) => format!(":{i}"),
+                                       Token::Ident(i, _) => i,
+                                       Token::Expression(i, _) => i.to_string(),
+                                       _ => panic!("unexpected token {x:?}"),
+                                   })
+                                   .collect::<Vec<_>>();
+
+        assert_eq!(got, vec![":Over#1", "Over#1", "JMP", "(Over#1+1)", ":Over#2", "Over#2", "JMP", "(Over#2+1)", "Over"]);
+    }
+
+    #[test]
+    fn expands_one_line_macro() {
+        let text = "%macro A %endm\n%macro B x %endm\nA B 1 2";
+
+        let got = expand_text(text).unwrap();
+
+        assert_eq!(got, vec![Token::Integer(2, Position{filename: "test".to_string(), line: 3, column: 7, expansion: None})]);
+    }
+
+    #[test]
+    fn error_on_too_big_expansion() {
+        let text = "%rep 1024 %rep 1024 %rep 2 0 %endrep %endrep %endrep";
+
+        let got = expand_text(text);
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:1: %rep expands to more than 1048576 tokens");
+    }
+
+    #[test]
+    fn error_on_unterminated_macro() {
+        let text = "1 %macro A\n2";
+
+        let got = expand_text(text);
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:3: unterminated %macro, expected %endm");
+    }
+
+    #[test]
+    fn error_on_duplicate_macro() {
+        let text = "%macro A\n%endm\n%macro A\n%endm";
+
+        let got = expand_text(text);
+
+        assert_eq!(got.unwrap_err().to_string(), "test:3:1: macro defined twice: A");
+    }
+
+    #[test]
+    fn error_on_missing_arguments() {
+        let text = "%macro A x y\nx y\n%endm\nA 1";
+
+        let got = expand_text(text);
+
+        assert_eq!(got.unwrap_err().to_string(), "test:4:1: macro A expects 2 arguments, got 1");
+    }
+
+    #[test]
+    fn error_on_recursive_macro() {
+        let text = "%macro A\nA\n%endm\nA";
+
+        let got = expand_text(text);
+
+        assert!(got.unwrap_err().to_string().starts_with("test:4:1 (in macro body at test:2:1"));
+    }
+}
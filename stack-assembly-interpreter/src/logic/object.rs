@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::models::error::{CollectErrors as _, error_at, bail_at};
+use crate::models::command::{Instruction, Opcode};
+use crate::models::expression::Expression;
+use crate::models::object::{Object, ObjectInstruction, Operand, Symbol, SymbolValue};
+use crate::models::token::Token;
+use super::binary::{Reader, Writer};
+use super::expression;
+use super::labels;
+use super::visibility;
+
+const MAGIC: &[u8; 4] = b"SAOB";
+const VERSION: u32 = 1;
+
+fn get_operand(expression: &Expression, absolute: &HashMap<&str, Opcode>) -> Result<Operand> {
+    if expression.symbols().iter().all(|name| absolute.contains_key(name.as_str())) {
+        return Ok(Operand::Value(expression::evaluate(expression, absolute)?))
+    }
+    Ok(Operand::Relocation(expression.clone()))
+}
+
+/// Translates preprocessed tokens of single file into relocatable object.
+/// References to labels and `PROGRAM_SIZE` are left as relocations, everything else is resolved.
+pub fn assemble(tokens: &[Token]) -> Result<Object> {
+    // NOTE: reports duplicate declarations the same way as for whole program
+    labels::get_labels(tokens)?;
+
+    let mut absolute = labels::get_default_labels();
+    let mut symbols = vec![];
+    for token in tokens {
+        match token {
+            Token::Declaration(name, pos) => symbols.push(Symbol{
+                name: name.clone(),
+                value: SymbolValue::Offset(0),
+                global: !visibility::is_private(name),
+                position: pos.clone(),
+            }),
+            Token::Constant(name, value, pos) => {
+                absolute.insert(name, *value);
+                symbols.push(Symbol{
+                    name: name.clone(),
+                    value: SymbolValue::Constant(*value),
+                    global: !visibility::is_private(name),
+                    position: pos.clone(),
+                });
+            },
+            _ => {},
+        }
+    }
+
+    let mut instructions = vec![];
+    let mut declared = 0;
+    for token in tokens {
+        let operand = match token {
+            Token::Declaration(_, _) => {
+                symbols[declared].value = SymbolValue::Offset(instructions.len() as i64);
+                declared += 1;
+                continue;
+            },
+            Token::Constant(_, _, _) => {
+                declared += 1;
+                continue;
+            },
+            Token::Integer(i, _) => Operand::Value(*i),
+            Token::Ident(name, _) => get_operand(&Expression::Symbol(name.clone()), &absolute)?,
+            Token::Expression(i, pos) => get_operand(i, &absolute).map_err(|err| error_at!(pos, "{err}"))?,
+            Token::Directive(i, pos) => bail_at!(pos, "unexpected directive: %{i}"),
+            Token::String(_, pos) => bail_at!(pos, "didn't expect string here"),
+        };
+        instructions.push(ObjectInstruction{operand, token: token.clone()});
+    }
+    Ok(Object{instructions, symbols})
+}
+
+/// Places objects one after another starting at address 256 and resolves relocations.
+/// Objects can reference only their own symbols and global symbols of other objects.
+pub fn link(objects: &[Object]) -> Result<Vec<Instruction>> {
+    let mut current = 256;
+    let mut labels = labels::get_default_labels();
+    // NOTE: index of object declaring each private symbol
+    let mut owners: HashMap<&str, usize> = HashMap::new();
+    let mut errors: Vec<Result<()>> = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        for symbol in &object.symbols {
+            if !symbol.global {
+                owners.insert(&symbol.name, i);
+            }
+            let (value, kind) = match symbol.value {
+                SymbolValue::Offset(offset) => (current + offset, "label declared twice"),
+                SymbolValue::Constant(value) => (value, "constant defined twice"),
+            };
+            if labels.insert(&symbol.name, value).is_some() {
+                errors.push(Err(error_at!(symbol.position, "{kind}: {}", visibility::source_name(&symbol.name))));
+            }
+        }
+        current += object.instructions.len() as i64;
+    }
+    labels.insert("PROGRAM_SIZE", current);
+    errors.into_iter().collect_errors::<()>()?;
+
+    objects.iter()
+           .enumerate()
+           .flat_map(|(i, object)| object.instructions.iter().map(move |x| (i, x)))
+           .map(|(i, x)| {
+               if let Operand::Relocation(expression) = &x.operand {
+                   if let Some(name) = expression.symbols().into_iter().find(|name| owners.get(name.as_str()).is_some_and(|owner| *owner != i)) {
+                       bail_at!(x.token.position(), "reference to private symbol of other object: {}", visibility::source_name(name))
+                   }
+               }
+               let opcode = match (&x.operand, &x.token) {
+                   (Operand::Value(value), _) => Ok(*value),
+                   (Operand::Relocation(_), Token::Ident(i, pos)) => labels.get(i.as_str())
+                                                                          .copied()
+                                                                          .ok_or_else(|| error_at!(pos, "undefined ident: \"{}\"", visibility::source_name(i))),
+                   (Operand::Relocation(i), token) => expression::evaluate(i, &labels)
+                                                                  .map_err(|err| error_at!(token.position(), "{err}")),
+               };
+               opcode.map(|opcode| Instruction{opcode, token: x.token.clone()})
+           })
+           .collect_errors()
+}
+
+pub fn serialize(object: &Object) -> Vec<u8> {
+    let mut writer = Writer::new(MAGIC, VERSION);
+    writer.usize(object.symbols.len());
+    for symbol in &object.symbols {
+        writer.str(&symbol.name);
+        match symbol.value {
+            SymbolValue::Offset(offset) => {
+                writer.u8(0);
+                writer.i64(offset);
+            },
+            SymbolValue::Constant(value) => {
+                writer.u8(1);
+                writer.i64(value);
+            },
+        }
+        writer.u8(symbol.global as u8);
+        writer.position(&symbol.position);
+    }
+    writer.usize(object.instructions.len());
+    for instruction in &object.instructions {
+        match &instruction.operand {
+            Operand::Value(value) => {
+                writer.u8(0);
+                writer.i64(*value);
+            },
+            Operand::Relocation(expression) => {
+                writer.u8(1);
+                writer.expression(expression);
+            },
+        }
+        writer.token(&instruction.token);
+    }
+    writer.into_bytes()
+}
+
+pub fn deserialize(bytes: &[u8]) -> Result<Object> {
+    let mut reader = Reader::new(bytes, MAGIC, VERSION)?;
+    let symbols = (0..reader.usize()?).map(|_| -> Result<_> {
+        let name = reader.str()?;
+        let value = match reader.u8()? {
+            0 => SymbolValue::Offset(reader.i64()?),
+            1 => SymbolValue::Constant(reader.i64()?),
+            tag => bail!("invalid symbol tag {tag}"),
+        };
+        let global = reader.u8()? != 0;
+        let position = reader.position()?;
+        Ok(Symbol{name, value, global, position})
+    }).collect::<Result<Vec<_>>>()?;
+    let instructions = (0..reader.usize()?).map(|_| -> Result<_> {
+        let operand = match reader.u8()? {
+            0 => Operand::Value(reader.i64()?),
+            1 => Operand::Relocation(reader.expression()?),
+            tag => bail!("invalid operand tag {tag}"),
+        };
+        let token = reader.token()?;
+        Ok(ObjectInstruction{operand, token})
+    }).collect::<Result<Vec<_>>>()?;
+    if !reader.is_empty() {
+        bail!("unexpected data after end of object")
+    }
+    Ok(Object{instructions, symbols})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::assembly::{self, Options, TextFile};
+
+    fn files() -> [TextFile; 2] {
+        [
+            TextFile{name: "main".to_owned(), text: String::from("%extern Print\nSize EQU 3\n:Loop Print CALL Loop+Size PROGRAM_SIZE 0 HALT")},
+            TextFile{name: "lib".to_owned(), text: String::from("%global Print\n:Print :.loop 'a' OUT .loop RET ADD+1")},
+        ]
+    }
+
+    #[test]
+    fn assembles_relocatable_object() {
+        let object = assembly::assemble_object(&files()[1], &Options::default()).unwrap();
+
+        let got = object.instructions.iter().map(|x| x.operand.clone()).collect::<Vec<_>>();
+
+        assert_eq!(got, vec![
+            Operand::Value(97),
+            Operand::Value(-44),
+            Operand::Relocation(Expression::Symbol("lib::Print.loop".to_string())),
+            Operand::Value(-13),
+            Operand::Value(0),
+        ]);
+        assert_eq!(object.symbols.iter().map(|x| (x.name.as_str(), &x.value, x.global)).collect::<Vec<_>>(), vec![
+            ("Print", &SymbolValue::Offset(0), true),
+            ("lib::Print.loop", &SymbolValue::Offset(0), false),
+        ]);
+    }
+
+    #[test]
+    fn linked_objects_match_assembled_program() {
+        let files = files();
+        let objects = files.iter()
+                           .map(|file| deserialize(&serialize(&assembly::assemble_object(file, &Options::default()).unwrap())).unwrap())
+                           .collect::<Vec<_>>();
+
+        let got = link(&objects);
+
+        assert_eq!(got.unwrap(), assembly::assembly(&files, &Options::default()).unwrap());
+    }
+
+    #[test]
+    fn error_on_unresolved_extern() {
+        let objects = [assembly::assemble_object(&files()[0], &Options::default()).unwrap()];
+
+        let got = link(&objects);
+
+        assert_eq!(got.unwrap_err().to_string(), "main:3:7: undefined ident: \"Print\"");
+    }
+
+    #[test]
+    fn error_on_reference_to_private_symbol_of_other_object() {
+        let [main, lib] = files().map(|file| assembly::assemble_object(&file, &Options::default()).unwrap());
+        let mut main = main;
+        main.instructions[0].operand = Operand::Relocation(Expression::Symbol("lib::Print.loop".to_string()));
+
+        let got = link(&[main, lib]);
+
+        assert_eq!(got.unwrap_err().to_string(), "main:3:7: reference to private symbol of other object: Print.loop");
+    }
+
+    #[test]
+    fn error_on_symbol_defined_in_several_objects() {
+        let lib = assembly::assemble_object(&files()[1], &Options::default()).unwrap();
+
+        let got = link(&[lib.clone(), lib]);
+
+        assert_eq!(got.unwrap_err().to_string(), "lib:2:1: label declared twice: Print
+lib:2:8: label declared twice: Print.loop");
+    }
+}
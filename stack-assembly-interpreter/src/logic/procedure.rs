@@ -0,0 +1,232 @@
+use anyhow::Result;
+
+use crate::models::error::{CollectErrors as _, error_at, bail_at};
+use crate::models::token::{Position, Token};
+
+/// Procedure frame. Prologue saves caller's `fp` below return address and points `fp` at it,
+/// so argument `i` of `n` is at `fp+1+n-i` and local `i` is at `fp-1-i`.
+struct Frame {
+    name: String,
+    args: usize,
+    locals: usize,
+}
+
+/// Generates tokens of space separated mnemonics and non-negative integers at `pos`.
+fn code(text: &str, pos: &Position) -> Vec<Token> {
+    text.split_whitespace()
+        .map(|word| match word.parse() {
+            Ok(value) => Token::Integer(value, pos.clone()),
+            Err(_) => Token::Ident(word.to_string(), pos.clone()),
+        })
+        .collect()
+}
+
+impl Frame {
+    fn prologue(&self, pos: &Position) -> Vec<Token> {
+        code(&format!("GETFP GETSP SETFP{}", " 0".repeat(self.locals)), pos)
+    }
+
+    /// Restores caller's `fp` and `sp`, dropping locals and arguments, and returns.
+    fn epilogue(&self, pos: &Position) -> Vec<Token> {
+        let n = self.args;
+        match n {
+            0 => code("GETFP SETSP SETFP RET", pos),
+            1 => code("GETFP SETSP SETFP RET2", pos),
+            // NOTE: return address and caller's fp are moved to slots of first arguments, then sp is set to them
+            _ => code(&format!("GETFP {} ADD GETFP 1 ADD LOAD SAVE GETFP {n} ADD GETFP LOAD SAVE GETFP {n} ADD SETSP SETFP RET", n+1), pos),
+        }
+    }
+
+    /// Returns tokens pushing address of `argN` or `localN`, `None` for other names.
+    fn address(&self, name: &str, pos: &Position) -> Option<Result<Vec<Token>>> {
+        let (kind, count, index) = if let Some(index) = name.strip_prefix("arg") {
+            ("arguments", self.args, index)
+        } else {
+            ("locals", self.locals, name.strip_prefix("local")?)
+        };
+        let index: usize = index.parse().ok()?;
+        if index >= count {
+            return Some(Err(error_at!(pos, "procedure {} has {count} {kind}, can't use {name}", self.name)))
+        }
+        let (offset, op) = match kind {
+            "arguments" => (self.args + 1 - index, "ADD"),
+            _ => (index + 1, "SUB"),
+        };
+        Some(Ok(code(&format!("GETFP {offset} {op}"), pos)))
+    }
+
+    fn routine(&self, name_pos: &Position, body: &[Token], pos: &Position, end_pos: &Position) -> Result<Vec<Token>> {
+        let mut res = vec![Token::Declaration(self.name.clone(), name_pos.clone())];
+        res.extend(self.prologue(pos));
+        res.extend(self.lower_body(body)?);
+        res.extend(self.epilogue(end_pos));
+        Ok(res)
+    }
+
+    fn lower_body(&self, mut body: &[Token]) -> Result<Vec<Token>> {
+        let mut res = vec![];
+        let mut errors: Vec<Result<()>> = vec![];
+        while let Some((token, tail)) = body.split_first() {
+            body = tail;
+            match token {
+                Token::Ident(name, pos) => match self.address(name, pos) {
+                    Some(Ok(address)) => {
+                        res.extend(address);
+                        res.extend(code("LOAD", pos));
+                    },
+                    Some(Err(err)) => errors.push(Err(err)),
+                    None => res.push(token.clone()),
+                },
+                Token::Directive(directive, pos) if directive == "to" => {
+                    let address = match tail.first() {
+                        Some(Token::Ident(name, name_pos)) => self.address(name, name_pos),
+                        _ => None,
+                    };
+                    match address {
+                        Some(Ok(address)) => {
+                            body = &tail[1..];
+                            res.extend(address);
+                            res.extend(code("SWAP SAVE", pos));
+                        },
+                        Some(Err(err)) => errors.push(Err(err)),
+                        None => errors.push(Err(error_at!(pos, "%to expects argument or local name"))),
+                    }
+                },
+                Token::Directive(directive, pos) if directive == "return" => res.extend(self.epilogue(pos)),
+                Token::Directive(directive, pos) if directive == "proc" => errors.push(Err(error_at!(pos, "nested procedures are not allowed"))),
+                _ => res.push(token.clone()),
+            }
+        }
+        errors.into_iter().collect_errors::<()>()?;
+        Ok(res)
+    }
+}
+
+fn parse_header(header: &[Token], pos: &Position) -> Result<(Frame, Position)> {
+    let (name, attributes) = header.split_first()
+                                   .ok_or_else(|| error_at!(pos, "expected procedure name after %proc"))?;
+    let (name, name_pos) = match name {
+        Token::Ident(name, name_pos) => (name.clone(), name_pos.clone()),
+        _ => bail_at!(name.position(), "expected procedure name, got {name:?}"),
+    };
+    let mut frame = Frame{name, args: 0, locals: 0};
+    for attribute in attributes {
+        let parsed = match attribute {
+            Token::Ident(attribute, _) => attribute.split_once('=')
+                                                   .and_then(|(key, value)| Some((key, value.parse::<usize>().ok()?))),
+            _ => None,
+        };
+        match parsed {
+            Some(("args", value)) => frame.args = value,
+            Some(("locals", value)) => frame.locals = value,
+            _ => bail_at!(attribute.position(), "expected args=N or locals=M"),
+        }
+    }
+    Ok((frame, name_pos))
+}
+
+/// Lowers `%proc Name args=N locals=M ... %endproc` blocks into routines declared as `Name`.
+/// Body sees arguments and locals as `arg0`, `local1` etc., `%to local1` stores top of stack into one,
+/// `%return` returns early. Return values are passed in `rv`, caller pushes `arg0` first.
+pub fn expand(tokens: Vec<Token>) -> Result<Vec<Token>> {
+    let mut res = vec![];
+    let mut errors: Vec<Result<()>> = vec![];
+    let mut tokens = tokens.as_slice();
+    while let Some((token, tail)) = tokens.split_first() {
+        tokens = tail;
+        match token {
+            Token::Directive(directive, pos) if directive == "proc" => {
+                let header_len = tail.iter()
+                                     .take_while(|x| x.position().same_line(pos))
+                                     .count();
+                let (header, tail) = tail.split_at(header_len);
+                let end = tail.iter()
+                              .position(|x| matches!(x, Token::Directive(directive, _) if directive == "endproc"))
+                              .ok_or_else(|| error_at!(pos, "unterminated %proc, expected %endproc"))?;
+                tokens = &tail[end+1..];
+                let lowered = parse_header(header, pos).and_then(|(frame, name_pos)|
+                    frame.routine(&name_pos, &tail[..end], pos, tail[end].position()));
+                match lowered {
+                    Ok(lowered) => res.extend(lowered),
+                    Err(err) => errors.push(Err(err)),
+                }
+            },
+            Token::Directive(directive, pos) if directive == "endproc" =>
+                errors.push(Err(error_at!(pos, "%endproc without matching %proc"))),
+            Token::Directive(directive, pos) if directive == "to" || directive == "return" =>
+                errors.push(Err(error_at!(pos, "%{directive} outside of %proc"))),
+            _ => res.push(token.clone()),
+        }
+    }
+    errors.into_iter().collect_errors::<()>()?;
+    Ok(res)
+}
+
+/// Lowers `body` of procedure `name` into routine, as if it was written in `%proc` block.
+pub fn lower(name: &str, args: usize, locals: usize, body: &[Token], pos: &Position) -> Result<Vec<Token>> {
+    Frame{name: name.to_string(), args, locals}.routine(pos, body, pos, pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::assembly::{self, Options, TextFile};
+    use crate::logic::stdio::Stdio;
+    use crate::logic::tokenize::tokenize;
+    use crate::logic::vm::Executor;
+    use crate::models::vm::VM;
+
+    fn lowered(text: &str) -> Result<String> {
+        Ok(expand(tokenize(text, "test")?)?.into_iter()
+                                           .map(|x| match x {
+                                               Token::Declaration(i, _) => format!(":{i}"),
+                                               Token::Ident(i, _) => i,
+                                               Token::Integer(i, _) => i.to_string(),
+                                               _ => panic!("unexpected token {x:?}"),
+                                           })
+                                           .collect::<Vec<_>>()
+                                           .join(" "))
+    }
+
+    fn run(text: &str) -> i64 {
+        let files = &[TextFile{name: "test".to_string(), text: text.to_string()}];
+        let instructions = assembly::assembly(files, &Options::default()).unwrap();
+        Executor{io: &mut Stdio::new()}.execute(VM::new(instructions)).unwrap()
+    }
+
+    #[test]
+    fn generates_prologue_epilogue_and_frame_accesses() {
+        let got = lowered("%proc Inc args=1 locals=1\n    arg0 1 ADD %to local0 local0 SETRV\n%endproc");
+
+        assert_eq!(got.unwrap(), ":Inc GETFP GETSP SETFP 0 GETFP 2 ADD LOAD 1 ADD GETFP 1 SUB SWAP SAVE GETFP 1 SUB LOAD SETRV \
+GETFP SETSP SETFP RET2");
+    }
+
+    #[test]
+    fn procedures_preserve_caller_frame_and_drop_arguments() {
+        let text = "
+            7 100 30 2 Sub3 CALL GETRV ADD HALT
+            %proc Sub3 args=3 locals=1
+                arg0 arg1 SUB %to local0
+                local0 arg2 Twice CALL GETRV SUB SETRV
+            %endproc
+            %proc Twice args=1
+                arg0 .zero JEQ
+                arg0 arg0 ADD SETRV %return
+                :.zero 0 SETRV
+            %endproc";
+
+        assert_eq!(run(text), 7 + (100-30-2*2));
+    }
+
+    #[test]
+    fn error_on_misused_frame_names() {
+        assert_eq!(lowered("%proc F args=1\narg1 local0\n%endproc").unwrap_err().to_string(), "test:2:1: procedure F has 1 arguments, can't use arg1
+test:2:6: procedure F has 0 locals, can't use local0");
+        assert_eq!(lowered("%proc F args=x\n%endproc").unwrap_err().to_string(), "test:1:9: expected args=N or locals=M");
+        assert_eq!(lowered("%proc F\n%proc G\n%endproc").unwrap_err().to_string(), "test:2:1: nested procedures are not allowed");
+        assert_eq!(lowered("%proc F 1").unwrap_err().to_string(), "test:1:1: unterminated %proc, expected %endproc");
+        assert_eq!(lowered("%to local0 %endproc").unwrap_err().to_string(), "test:1:1: %to outside of %proc
+test:1:12: %endproc without matching %proc");
+    }
+}
@@ -5,20 +5,27 @@ use std::io::{self, BufRead};
 use crate::models::command::{Input, Output};
 
 pub struct Stdio {
+    reader: Box<dyn BufRead>,
     buffer: String,
 }
 
 impl Stdio {
     pub fn new() -> Self {
-        Self { buffer: "".to_string() }
+        Self::from_reader(io::stdin().lock())
+    }
+
+    /// Reads input from `reader` instead of standard input.
+    pub fn from_reader(reader: impl BufRead + 'static) -> Self {
+        Self { reader: Box::new(reader), buffer: "".to_string() }
     }
 }
 
 impl Input for Stdio {
     fn get_char(&mut self) -> Result<i64> {
         while self.buffer.is_empty() {
-            let mut stdin = io::stdin().lock();
-            stdin.read_line(&mut self.buffer)?;
+            if self.reader.read_line(&mut self.buffer)? == 0 {
+                return Ok(-1)
+            }
         }
         let c = self.buffer.chars().next().ok_or_else(|| anyhow!("empty buffer"))?;
         self.buffer = self.buffer.get(1..).unwrap_or_default().to_string();
@@ -27,10 +34,71 @@ impl Input for Stdio {
     }
 }
 
+fn to_char(c: i64) -> Result<char> {
+    char::from_u32(u32::try_from(c)?).ok_or_else(|| anyhow!("invalid character code {c}"))
+}
+
 impl Output for Stdio {
     fn print_char(&self, c: i64) -> Result<()> {
-        let c = char::from_u32(u32::try_from(c)?).ok_or_else(|| anyhow!("invalid character code {c}"))?;
-        print!("{c}");
+        print!("{}", to_char(c)?);
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub mod testing {
+    use std::cell::RefCell;
+
+    use anyhow::Result;
+
+    use crate::logic::vm::Executor;
+    use crate::models::command::{Input, Instruction, Output, ReturnCode};
+    use crate::models::vm::VM;
+    use super::to_char;
+
+    /// Input and output kept in memory.
+    pub struct FakeIo {
+        input: Vec<i64>,
+        pub output: RefCell<String>,
+    }
+
+    impl FakeIo {
+        pub fn new(input: &str) -> Self {
+            Self { input: input.chars().rev().map(|c| c as i64).collect(), output: RefCell::new(String::new()) }
+        }
+    }
+
+    impl Input for FakeIo {
+        fn get_char(&mut self) -> Result<i64> {
+            Ok(self.input.pop().unwrap_or(-1))
+        }
+    }
+
+    impl Output for FakeIo {
+        fn print_char(&self, c: i64) -> Result<()> {
+            self.output.borrow_mut().push(to_char(c)?);
+            Ok(())
+        }
+    }
+
+    /// Runs program with `input`, returns its output and return code.
+    pub fn run(instructions: Vec<Instruction>, input: &str) -> Result<(String, ReturnCode)> {
+        let mut io = FakeIo::new(input);
+        let rc = Executor{io: &mut io}.execute(VM::new(instructions))?;
+        Ok((io.output.into_inner(), rc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returns_minus_one_at_end_of_input() {
+        let mut stdio = Stdio::from_reader("a\nb".as_bytes());
+
+        let got = (0..5).map(|_| stdio.get_char().unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(got, vec![97, 10, 98, -1, -1]);
+    }
+}
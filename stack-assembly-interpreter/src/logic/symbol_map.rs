@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::models::command::Opcode;
+use crate::models::token::{Position, Token};
+use super::labels;
+use super::visibility;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SymbolKind {
+    Label,
+    Constant,
+    Builtin,
+    ProgramSize,
+}
+
+impl SymbolKind {
+    fn name(self) -> &'static str {
+        match self {
+            SymbolKind::Label => "label",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Builtin => "builtin",
+            SymbolKind::ProgramSize => "program_size",
+        }
+    }
+
+    /// Type letter in `nm` style output, lowercase for private symbols.
+    fn letter(self, global: bool) -> char {
+        let letter = match self {
+            SymbolKind::Label => 'T',
+            SymbolKind::Constant => 'A',
+            SymbolKind::Builtin => 'B',
+            SymbolKind::ProgramSize => 'S',
+        };
+        if global { letter } else { letter.to_ascii_lowercase() }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct SymbolInfo {
+    /// Name as written in source.
+    pub name: String,
+    /// Whether symbol is visible from other files, see [`visibility::resolve`].
+    pub global: bool,
+    pub value: Opcode,
+    pub kind: SymbolKind,
+    /// Position of declaration, absent for builtins and `PROGRAM_SIZE`.
+    pub position: Option<Position>,
+}
+
+/// Describes every symbol of `labels` map computed for `tokens`, ordered by value.
+pub fn collect(tokens: &[Token], labels: &HashMap<&str, Opcode>) -> Vec<SymbolInfo> {
+    let mut res: Vec<SymbolInfo> = labels.iter()
+                                         .map(|(name, value)| SymbolInfo{
+                                             name: name.to_string(),
+                                             global: !visibility::is_private(name),
+                                             value: *value,
+                                             kind: if *name == "PROGRAM_SIZE" { SymbolKind::ProgramSize } else { SymbolKind::Builtin },
+                                             position: None,
+                                         })
+                                         .collect();
+    for token in tokens {
+        let (name, kind, pos) = match token {
+            Token::Declaration(name, pos) => (name, SymbolKind::Label, pos),
+            Token::Constant(name, _, pos) => (name, SymbolKind::Constant, pos),
+            _ => continue,
+        };
+        if let Some(symbol) = res.iter_mut().find(|x| x.name == *name) {
+            symbol.kind = kind;
+            symbol.position = Some(pos.clone());
+        }
+    }
+    debug_assert!(res.iter().all(|x| x.position.is_some() || labels::is_builtin(&x.name)));
+    for symbol in &mut res {
+        symbol.name = visibility::source_name(&symbol.name).to_string();
+    }
+    res.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+    res
+}
+
+fn position_json(pos: &Position) -> Value {
+    json!({
+        "file": pos.filename,
+        "line": pos.line,
+        "column": pos.column,
+        "expansion": pos.expansion.as_deref().map(position_json),
+    })
+}
+
+pub fn to_json(symbols: &[SymbolInfo]) -> String {
+    let symbols = symbols.iter()
+                         .map(|x| json!({
+                             "name": x.name,
+                             "visibility": if x.global { "global" } else { "private" },
+                             "value": x.value,
+                             "kind": x.kind.name(),
+                             "position": x.position.as_ref().map(position_json),
+                         }))
+                         .collect::<Vec<_>>();
+    serde_json::to_string_pretty(&symbols).unwrap() + "\n"
+}
+
+/// One symbol per line: value, type letter (`T` label, `A` constant, `B` builtin, `S` `PROGRAM_SIZE`,
+/// lowercase for private symbols), name and position.
+pub fn to_text(symbols: &[SymbolInfo]) -> String {
+    symbols.iter()
+           .map(|x| {
+               let line = format!("{:>8} {} {} {}", x.value, x.kind.letter(x.global), x.name,
+                                  x.position.as_ref().map(Position::to_string).unwrap_or_default());
+               line.trim_end().to_string() + "\n"
+           })
+           .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::assembly::{Options, Program, TextFile};
+
+    fn symbols(text: &str) -> Vec<SymbolInfo> {
+        let files = [
+            TextFile{name: "test".to_owned(), text: text.to_owned()},
+            TextFile{name: "lib".to_owned(), text: String::from("%global Print\n:Print :.loop .loop RET")},
+        ];
+        Program::preprocess(&files, &Options::default()).unwrap().symbol_map().unwrap()
+    }
+
+    #[test]
+    fn exports_symbols_as_text() {
+        let got = to_text(&symbols("%global Main\nSize EQU 300\n:Main HALT :End"));
+
+        assert_eq!(got.lines().filter(|x| !x.contains(" B ")).collect::<Vec<_>>(), vec![
+            "     256 T Main test:3:1",
+            "     257 t End test:3:12",
+            "     257 T Print lib:2:1",
+            "     257 t Print.loop lib:2:8",
+            "     259 S PROGRAM_SIZE",
+            "     300 a Size test:2:1",
+        ]);
+    }
+
+    #[test]
+    fn exports_symbols_as_json() {
+        let got = symbols(":Main ADD");
+
+        let got: Value = serde_json::from_str(&to_json(&got)).unwrap();
+
+        let got = got.as_array().unwrap();
+        assert_eq!(got.len(), labels::get_default_labels().len() + 4);
+        assert!(got.contains(&json!({"name": "ADD", "visibility": "global", "value": -1, "kind": "builtin", "position": null})));
+        assert!(got.contains(&json!({
+            "name": "Main", "visibility": "private", "value": 256, "kind": "label",
+            "position": {"file": "test", "line": 1, "column": 1, "expansion": null},
+        })));
+    }
+}
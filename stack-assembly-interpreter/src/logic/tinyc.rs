@@ -0,0 +1,844 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::models::command::Instruction;
+use crate::models::error::{CollectErrors as _, error_at, bail_at};
+use crate::models::token::{Position, Token};
+use crate::models::vm::VM;
+use super::assembly::{self, TextFile};
+use super::{control, procedure, tokenize};
+
+const PUNCTUATION: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=",
+    "+", "-", "*", "/", "%", "<", ">", "!", "=", "(", ")", "{", "}", "[", "]", ";", ",",
+];
+const KEYWORDS: &[&str] = &["int", "void", "if", "else", "while", "return"];
+
+#[derive(PartialEq, Debug, Clone)]
+enum Lexeme {
+    Number(i64),
+    Name(String),
+    Punct(&'static str),
+    End,
+}
+
+fn lex(file: &TextFile) -> Result<Vec<(Lexeme, Position)>> {
+    let mut res = vec![];
+    let chars: Vec<char> = file.text.chars().collect();
+    let (mut i, mut line, mut line_start) = (0, 1, 0);
+    while i < chars.len() {
+        let pos = Position{filename: file.name.clone(), line, column: i - line_start + 1, expansion: None};
+        let rest: String = chars[i..chars.len().min(i+2)].iter().collect();
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            line_start = i + 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if rest == "//" {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if rest == "/*" {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i+1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                    line_start = i + 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                bail_at!(pos, "unterminated comment")
+            }
+            i += 2;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = tokenize::parse_integer(&text).map_err(|err| error_at!(pos, "invalid number {text}: {err}"))?;
+            res.push((Lexeme::Number(value), pos));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            res.push((Lexeme::Name(chars[start..i].iter().collect()), pos));
+        } else if c == '\'' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i] != '\'' && chars[i] != '\n' {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+            let literal: String = chars[start..i.min(chars.len())].iter().collect();
+            let value = tokenize::decode_char(&literal).map_err(|err| error_at!(pos, "invalid char literal {literal}: {err}"))?;
+            res.push((Lexeme::Number(value), pos));
+        } else if let Some(punct) = PUNCTUATION.iter().find(|x| rest.starts_with(**x)) {
+            i += punct.len();
+            res.push((Lexeme::Punct(punct), pos));
+        } else {
+            bail_at!(pos, "unexpected character: {c:?}")
+        }
+    }
+    let pos = Position{filename: file.name.clone(), line, column: i - line_start + 1, expansion: None};
+    res.push((Lexeme::End, pos));
+    Ok(res)
+}
+
+#[derive(Debug)]
+enum ExprKind {
+    Number(i64),
+    Var(String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+struct Expr {
+    kind: ExprKind,
+    pos: Position,
+}
+
+enum Stmt {
+    Declare{name: String, size: Option<usize>, init: Option<Expr>, pos: Position},
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    Return(Option<Expr>, Position),
+    /// Statements in braces at position of `{`.
+    Block(Vec<Stmt>, Position),
+    Expr(Expr),
+}
+
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+    pos: Position,
+}
+
+struct Global {
+    name: String,
+    size: Option<usize>,
+    init: Option<i64>,
+    pos: Position,
+}
+
+/// Binary operators from lowest to highest precedence.
+const PRECEDENCE: &[&[&str]] = &[&["||"], &["&&"], &["==", "!="], &["<", "<=", ">", ">="], &["+", "-"], &["*", "/", "%"]];
+
+/// Limit on nesting of expressions and statements, so that crafted program can't overflow stack.
+const MAX_NESTING_DEPTH: usize = 128;
+
+struct Parser {
+    lexemes: Vec<(Lexeme, Position)>,
+    i: usize,
+    /// Nesting of expression or statement being parsed, chained operators nest too.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Lexeme {
+        &self.lexemes[self.i].0
+    }
+
+    fn pos(&self) -> Position {
+        self.lexemes[self.i].1.clone()
+    }
+
+    fn next(&mut self) -> (Lexeme, Position) {
+        let res = self.lexemes[self.i].clone();
+        if res.0 != Lexeme::End {
+            self.i += 1;
+        }
+        res
+    }
+
+    fn describe(lexeme: &Lexeme) -> String {
+        match lexeme {
+            Lexeme::Number(i) => i.to_string(),
+            Lexeme::Name(name) => name.clone(),
+            Lexeme::Punct(punct) => punct.to_string(),
+            Lexeme::End => "end of file".to_string(),
+        }
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let matches = matches!(self.peek(), Lexeme::Punct(x) if *x == punct);
+        if matches {
+            self.next();
+        }
+        matches
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        if !self.eat(punct) {
+            bail_at!(self.pos(), "expected {punct}, got {}", Self::describe(self.peek()))
+        }
+        Ok(())
+    }
+
+    fn deeper(&mut self, what: &str) -> Result<()> {
+        if self.depth == MAX_NESTING_DEPTH {
+            bail_at!(self.pos(), "{what} nested deeper than {MAX_NESTING_DEPTH} levels")
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Parses nested expression or statement, failing if nesting is too deep.
+    fn nested<T>(&mut self, what: &str, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.deeper(what)?;
+        let res = parse(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let matches = matches!(self.peek(), Lexeme::Name(x) if x == keyword);
+        if matches {
+            self.next();
+        }
+        matches
+    }
+
+    fn name(&mut self) -> Result<(String, Position)> {
+        match self.next() {
+            (Lexeme::Name(name), pos) if !KEYWORDS.contains(&name.as_str()) => Ok((name, pos)),
+            (lexeme, pos) => bail_at!(pos, "expected name, got {}", Self::describe(&lexeme)),
+        }
+    }
+
+    fn size(&mut self) -> Result<Option<usize>> {
+        if !self.eat("[") {
+            return Ok(None)
+        }
+        let size = match self.next() {
+            (Lexeme::Number(size), pos) if size > 0 => usize::try_from(size).ok()
+                                                                             .filter(|x| *x <= VM::MAX_CODE_SIZE)
+                                                                             .ok_or_else(|| error_at!(pos, "array of {size} cells doesn't fit in VM memory"))?,
+            (lexeme, pos) => bail_at!(pos, "expected positive array size, got {}", Self::describe(&lexeme)),
+        };
+        self.expect("]")?;
+        Ok(Some(size))
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let (lexeme, pos) = self.next();
+        let kind = match lexeme {
+            Lexeme::Number(value) => ExprKind::Number(value),
+            Lexeme::Name(name) if !KEYWORDS.contains(&name.as_str()) => match self.eat("(") {
+                true => {
+                    let mut args = vec![];
+                    if !self.eat(")") {
+                        loop {
+                            args.push(self.expression()?);
+                            if self.eat(")") {
+                                break
+                            }
+                            self.expect(",")?;
+                        }
+                    }
+                    ExprKind::Call(name, args)
+                },
+                false => ExprKind::Var(name),
+            },
+            Lexeme::Punct("(") => {
+                let expr = self.expression()?;
+                self.expect(")")?;
+                return Ok(expr)
+            },
+            Lexeme::Punct(op @ ("-" | "!")) => ExprKind::Unary(op, Box::new(self.nested("expression", Self::primary)?)),
+            _ => bail_at!(pos, "expected expression, got {}", Self::describe(&lexeme)),
+        };
+        let mut expr = Expr{kind, pos};
+        let depth = self.depth;
+        while self.eat("[") {
+            self.deeper("expression")?;
+            let pos = expr.pos.clone();
+            let index = self.expression()?;
+            self.expect("]")?;
+            expr = Expr{kind: ExprKind::Index(Box::new(expr), Box::new(index)), pos};
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    /// Parses operators of precedence `level` and higher.
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        let mut lhs = self.primary()?;
+        let depth = self.depth;
+        while let Lexeme::Punct(op) = *self.peek() {
+            let Some(op_level) = PRECEDENCE.iter().position(|ops| ops.contains(&op)).filter(|x| *x >= level) else {
+                break
+            };
+            self.deeper("expression")?;
+            let pos = self.next().1;
+            let rhs = self.binary(op_level+1)?;
+            lhs = Expr{kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), pos};
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    fn expression(&mut self) -> Result<Expr> {
+        self.nested("expression", Self::assignment)
+    }
+
+    fn assignment(&mut self) -> Result<Expr> {
+        let lhs = self.binary(0)?;
+        if !matches!(self.peek(), Lexeme::Punct("=")) {
+            return Ok(lhs)
+        }
+        let pos = self.next().1;
+        if !matches!(lhs.kind, ExprKind::Var(_) | ExprKind::Index(_, _)) {
+            bail_at!(pos, "left side of assignment must be variable or array element")
+        }
+        Ok(Expr{kind: ExprKind::Assign(Box::new(lhs), Box::new(self.expression()?)), pos})
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        self.expect("{")?;
+        let mut res = vec![];
+        while !self.eat("}") {
+            if *self.peek() == Lexeme::End {
+                bail_at!(self.pos(), "expected }}, got end of file")
+            }
+            res.push(self.statement()?);
+        }
+        Ok(res)
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        self.nested("statement", Self::simple_statement)
+    }
+
+    fn simple_statement(&mut self) -> Result<Stmt> {
+        let pos = self.pos();
+        if self.keyword("int") {
+            let (name, pos) = self.name()?;
+            let size = self.size()?;
+            let init = match self.eat("=") {
+                true if size.is_some() => bail_at!(pos, "arrays can't be initialized"),
+                true => Some(self.expression()?),
+                false => None,
+            };
+            self.expect(";")?;
+            return Ok(Stmt::Declare{name, size, init, pos})
+        }
+        if self.keyword("if") {
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(")")?;
+            let then = Box::new(self.statement()?);
+            let otherwise = match self.keyword("else") {
+                true => Some(Box::new(self.statement()?)),
+                false => None,
+            };
+            return Ok(Stmt::If(condition, then, otherwise))
+        }
+        if self.keyword("while") {
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(")")?;
+            return Ok(Stmt::While(condition, Box::new(self.statement()?)))
+        }
+        if self.keyword("return") {
+            let value = match self.eat(";") {
+                true => return Ok(Stmt::Return(None, pos)),
+                false => self.expression()?,
+            };
+            self.expect(";")?;
+            return Ok(Stmt::Return(Some(value), pos))
+        }
+        if matches!(self.peek(), Lexeme::Punct("{")) {
+            return Ok(Stmt::Block(self.block()?, pos))
+        }
+        let expr = self.expression()?;
+        self.expect(";")?;
+        Ok(Stmt::Expr(expr))
+    }
+
+    fn program(&mut self, functions: &mut Vec<Function>, globals: &mut Vec<Global>) -> Result<()> {
+        while *self.peek() != Lexeme::End {
+            if !self.keyword("int") && !self.keyword("void") {
+                bail_at!(self.pos(), "expected int or void, got {}", Self::describe(self.peek()))
+            }
+            let (name, pos) = self.name()?;
+            if !self.eat("(") {
+                let size = self.size()?;
+                let init = match self.eat("=") {
+                    true => {
+                        let negative = self.eat("-");
+                        match self.next() {
+                            (Lexeme::Number(value), _) if size.is_none() => Some(if negative { -value } else { value }),
+                            (_, pos) => bail_at!(pos, "global can only be initialized with number"),
+                        }
+                    },
+                    false => None,
+                };
+                self.expect(";")?;
+                globals.push(Global{name, size, init, pos});
+                continue
+            }
+            let mut params = vec![];
+            let empty = self.eat(")") || (self.keyword("void") && self.eat(")"));
+            if !empty {
+                loop {
+                    if !self.keyword("int") {
+                        bail_at!(self.pos(), "expected int parameter, got {}", Self::describe(self.peek()))
+                    }
+                    params.push(self.name()?.0);
+                    if self.eat(")") {
+                        break
+                    }
+                    self.expect(",")?;
+                }
+            }
+            let body = self.block()?;
+            functions.push(Function{name, params, body, pos});
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Var {
+    Arg(usize),
+    Local(usize),
+    /// Array allocated on stack, whose address is kept in local `slot`.
+    LocalArray{slot: usize, size: usize},
+    Global(usize),
+    GlobalArray(usize),
+}
+
+/// Label of function, builtin mnemonics can be used as function names.
+fn label(name: &str) -> String {
+    format!("c#{name}")
+}
+
+struct Generator<'a> {
+    out: Vec<Token>,
+    globals: &'a HashMap<String, Var>,
+    /// Parameter counts of functions by name.
+    arities: &'a HashMap<String, usize>,
+    scopes: Vec<HashMap<String, Var>>,
+    locals: usize,
+    /// Cells of local arrays allocated in current scopes.
+    arrays: usize,
+    errors: Vec<Result<()>>,
+}
+
+impl Generator<'_> {
+    fn emit(&mut self, text: &str, pos: &Position) -> Result<()> {
+        tokenize::emit(&mut self.out, text, pos)
+    }
+
+    fn lookup(&mut self, name: &str, pos: &Position) -> Option<Var> {
+        let var = self.scopes.iter()
+                             .rev()
+                             .find_map(|scope| scope.get(name))
+                             .or_else(|| self.globals.get(name))
+                             .copied();
+        if var.is_none() {
+            self.errors.push(Err(error_at!(pos, "undefined variable: {name}")));
+        }
+        var
+    }
+
+    /// Pushes address of array element or variable stored in memory.
+    fn address(&mut self, expr: &Expr) -> Result<()> {
+        match &expr.kind {
+            ExprKind::Index(array, index) => {
+                self.expression(array)?;
+                self.expression(index)?;
+                self.emit("ADD", &expr.pos)?;
+            },
+            ExprKind::Var(name) => if let Some(Var::Global(offset)) = self.lookup(name, &expr.pos) {
+                self.emit(&format!("(PROGRAM_SIZE+{offset})"), &expr.pos)?;
+            },
+            _ => unreachable!("parser accepts only variables and array elements as lvalues"),
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<()> {
+        let pos = &expr.pos;
+        match &expr.kind {
+            ExprKind::Number(value) if *value >= 0 => self.emit(&value.to_string(), pos)?,
+            ExprKind::Number(value) => self.emit(&format!("{} NEG", value.unsigned_abs()), pos)?,
+            ExprKind::Var(name) => match self.lookup(name, pos) {
+                Some(Var::Arg(i)) => self.emit(&format!("arg{i}"), pos)?,
+                Some(Var::Local(i)) => self.emit(&format!("local{i}"), pos)?,
+                Some(Var::LocalArray{slot, ..}) => self.emit(&format!("local{slot}"), pos)?,
+                Some(Var::Global(offset)) => self.emit(&format!("(PROGRAM_SIZE+{offset}) LOAD"), pos)?,
+                Some(Var::GlobalArray(offset)) => self.emit(&format!("(PROGRAM_SIZE+{offset})"), pos)?,
+                None => {},
+            },
+            ExprKind::Index(_, _) => {
+                self.address(expr)?;
+                self.emit("LOAD", pos)?;
+            },
+            ExprKind::Call(name, args) => {
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                match (name.as_str(), args.len()) {
+                    ("putchar", 1) => self.emit("DUP OUT", pos)?,
+                    ("getchar", 0) => self.emit("IN", pos)?,
+                    _ => match self.arities.get(name) {
+                        Some(arity) if *arity == args.len() => {
+                            self.out.push(Token::Ident(label(name), pos.clone()));
+                            self.emit("CALL GETRV", pos)?;
+                        },
+                        Some(arity) => self.errors.push(Err(error_at!(pos, "function {name} expects {arity} arguments, got {}", args.len()))),
+                        None => self.errors.push(Err(error_at!(pos, "undefined function: {name}"))),
+                    },
+                }
+            },
+            ExprKind::Unary(op, x) => {
+                self.expression(x)?;
+                self.emit(if *op == "-" { "NEG" } else { "IF 0 ELSE 1 THEN" }, pos)?;
+            },
+            ExprKind::Binary("&&", x, y) => {
+                self.expression(x)?;
+                self.emit("IF", pos)?;
+                self.expression(y)?;
+                self.emit("IF 1 ELSE 0 THEN ELSE 0 THEN", pos)?;
+            },
+            ExprKind::Binary("||", x, y) => {
+                self.expression(x)?;
+                self.emit("IF 1 ELSE", pos)?;
+                self.expression(y)?;
+                self.emit("IF 1 ELSE 0 THEN THEN", pos)?;
+            },
+            ExprKind::Binary(op, x, y) => {
+                self.expression(x)?;
+                self.expression(y)?;
+                let code = match *op {
+                    "+" => "ADD",
+                    "-" => "SUB",
+                    "*" => "MUL",
+                    "/" => "DIV",
+                    "%" => "MOD",
+                    // NOTE: CMP gives sign of difference, it's turned into 0 or 1
+                    "==" => "CMP IF 0 ELSE 1 THEN",
+                    "!=" => "CMP IF 1 ELSE 0 THEN",
+                    "<" => "CMP 1 ADD IF 0 ELSE 1 THEN",
+                    ">=" => "CMP 1 ADD IF 1 ELSE 0 THEN",
+                    ">" => "CMP 1 SUB IF 0 ELSE 1 THEN",
+                    _ => "CMP 1 SUB IF 1 ELSE 0 THEN",
+                };
+                self.emit(code, pos)?;
+            },
+            ExprKind::Assign(lhs, value) => match &lhs.kind {
+                ExprKind::Var(name) => match self.lookup(name, &lhs.pos) {
+                    Some(Var::Arg(i)) => {
+                        self.expression(value)?;
+                        self.emit(&format!("DUP %to arg{i}"), pos)?;
+                    },
+                    Some(Var::Local(i)) => {
+                        self.expression(value)?;
+                        self.emit(&format!("DUP %to local{i}"), pos)?;
+                    },
+                    Some(Var::Global(_)) => {
+                        self.address(lhs)?;
+                        self.expression(value)?;
+                        self.emit("SWAP OVER SAVE", pos)?;
+                    },
+                    Some(_) => self.errors.push(Err(error_at!(pos, "can't assign to array {name}"))),
+                    None => {},
+                },
+                _ => {
+                    self.address(lhs)?;
+                    self.expression(value)?;
+                    self.emit("SWAP OVER SAVE", pos)?;
+                },
+            },
+        }
+        Ok(())
+    }
+
+    fn declare(&mut self, name: &str, var: Var, pos: &Position) {
+        let scope = self.scopes.last_mut().unwrap();
+        if scope.insert(name.to_string(), var).is_some() {
+            self.errors.push(Err(error_at!(pos, "variable declared twice in the same scope: {name}")));
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            // NOTE: array is allocated by moving sp, its address is kept in local
+            Stmt::Declare{name, size: Some(size), pos, ..} => {
+                match self.arrays.checked_add(*size).filter(|x| *x <= VM::MAX_CODE_SIZE) {
+                    Some(arrays) => self.arrays = arrays,
+                    None => bail_at!(pos, "local array {name} doesn't fit in VM memory"),
+                }
+                self.emit(&format!("GETSP {size} SUB SETSP GETSP %to local{}", self.locals), pos)?;
+                self.locals += 1;
+                self.declare(name, Var::LocalArray{slot: self.locals - 1, size: *size}, pos);
+            },
+            Stmt::Declare{name, size: None, init, pos} => {
+                if let Some(init) = init {
+                    self.expression(init)?;
+                    self.emit(&format!("%to local{}", self.locals), pos)?;
+                }
+                self.locals += 1;
+                self.declare(name, Var::Local(self.locals - 1), pos);
+            },
+            Stmt::If(condition, then, otherwise) => {
+                self.expression(condition)?;
+                self.emit("IF", &condition.pos)?;
+                self.statement(then)?;
+                if let Some(otherwise) = otherwise {
+                    self.emit("ELSE", &condition.pos)?;
+                    self.statement(otherwise)?;
+                }
+                self.emit("THEN", &condition.pos)?;
+            },
+            Stmt::While(condition, body) => {
+                self.emit("BEGIN", &condition.pos)?;
+                self.expression(condition)?;
+                self.emit("WHILE", &condition.pos)?;
+                self.statement(body)?;
+                self.emit("REPEAT", &condition.pos)?;
+            },
+            Stmt::Return(value, pos) => {
+                if let Some(value) = value {
+                    self.expression(value)?;
+                    self.emit("SETRV", pos)?;
+                }
+                self.emit("%return", pos)?;
+            },
+            Stmt::Block(body, pos) => {
+                self.scopes.push(HashMap::new());
+                for stmt in body {
+                    self.statement(stmt)?;
+                }
+                let arrays = self.scopes.pop()
+                                        .unwrap()
+                                        .values()
+                                        .map(|var| match var {
+                                            Var::LocalArray{size, ..} => *size,
+                                            _ => 0,
+                                        })
+                                        .sum::<usize>();
+                if arrays > 0 {
+                    self.arrays -= arrays;
+                    self.emit(&format!("GETSP {arrays} ADD SETSP"), pos)?;
+                }
+            },
+            Stmt::Expr(expr) => {
+                self.expression(expr)?;
+                self.emit("DROP", &expr.pos)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Allocates globals in memory after the code, in order of declaration.
+fn allocate(globals: &[Global], arities: &HashMap<String, usize>) -> Result<(HashMap<String, Var>, usize)> {
+    let mut res = HashMap::new();
+    let mut size = 0;
+    let mut errors: Vec<Result<()>> = vec![];
+    for global in globals {
+        let var = match global.size {
+            Some(_) => Var::GlobalArray(size),
+            None => Var::Global(size),
+        };
+        if res.insert(global.name.clone(), var).is_some() || arities.contains_key(&global.name) {
+            errors.push(Err(error_at!(global.pos, "name declared twice: {}", global.name)));
+        }
+        match size.checked_add(global.size.unwrap_or(1)).filter(|x| *x <= VM::MAX_CODE_SIZE) {
+            Some(total) => size = total,
+            None => {
+                errors.push(Err(error_at!(global.pos, "global {} doesn't fit in VM memory", global.name)));
+                break
+            },
+        }
+    }
+    errors.into_iter().collect_errors::<()>()?;
+    Ok((res, size))
+}
+
+/// Generates tokens of program calling `main` and exiting with its return value.
+fn generate(functions: &[Function], globals: &[Global]) -> Result<Vec<Token>> {
+    let mut arities = HashMap::new();
+    let mut errors: Vec<Result<()>> = vec![];
+    for function in functions {
+        if arities.insert(function.name.clone(), function.params.len()).is_some() || ["putchar", "getchar"].contains(&function.name.as_str()) {
+            errors.push(Err(error_at!(function.pos, "function defined twice: {}", function.name)));
+        }
+    }
+    let (globals_map, size) = allocate(globals, &arities)?;
+    let main = functions.iter()
+                        .find(|x| x.name == "main")
+                        .ok_or_else(|| anyhow!("no main function"))?;
+
+    let mut generator = Generator{out: vec![], globals: &globals_map, arities: &arities, scopes: vec![], locals: 0, arrays: 0, errors: vec![]};
+    let pos = &main.pos;
+    // NOTE: globals are zeroed in loop, as memory can't be read before it's written
+    if size > 0 {
+        generator.emit(&format!("(PROGRAM_SIZE) BEGIN DUP (PROGRAM_SIZE+{size}) CMP WHILE DUP 0 SAVE 1 ADD REPEAT DROP"), pos)?;
+    }
+    for global in globals {
+        if let (Some(init), Some(Var::Global(offset))) = (global.init, globals_map.get(&global.name)) {
+            generator.emit(&format!("(PROGRAM_SIZE+{offset})"), &global.pos)?;
+            generator.expression(&Expr{kind: ExprKind::Number(init), pos: global.pos.clone()})?;
+            generator.emit("SAVE", &global.pos)?;
+        }
+    }
+    generator.out.extend(std::iter::repeat_n(Token::Integer(0, pos.clone()), main.params.len()));
+    generator.out.push(Token::Ident(label("main"), pos.clone()));
+    generator.emit("CALL GETRV HALT", pos)?;
+
+    for function in functions {
+        let start = generator.out.len();
+        generator.scopes = vec![function.params.iter()
+                                               .enumerate()
+                                               .map(|(i, param)| (param.clone(), Var::Arg(i)))
+                                               .collect()];
+        generator.locals = 0;
+        generator.arrays = 0;
+        generator.scopes.push(HashMap::new());
+        for stmt in &function.body {
+            generator.statement(stmt)?;
+        }
+        let body = generator.out.split_off(start);
+        match procedure::lower(&label(&function.name), function.params.len(), generator.locals, &body, &function.pos) {
+            Ok(routine) => generator.out.extend(routine),
+            Err(err) => errors.push(Err(err)),
+        }
+    }
+    errors.extend(generator.errors);
+    errors.into_iter().collect_errors::<()>()?;
+    Ok(generator.out)
+}
+
+/// Compiles program in tiny C-like language: `int` variables and arrays, functions, `if`, `while`,
+/// `putchar` and `getchar`. Program exits with return value of `main`.
+/// Globals are zeroed, local arrays are allocated on stack until end of their block and aren't initialized.
+pub fn compile(files: &[TextFile]) -> Result<Vec<Instruction>> {
+    let mut functions = vec![];
+    let mut globals = vec![];
+    for file in files {
+        let mut parser = Parser{lexemes: lex(file)?, i: 0, depth: 0};
+        parser.program(&mut functions, &mut globals)?;
+    }
+    assembly::assemble_tokens(&control::lower(generate(&functions, &globals)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::stdio::testing;
+
+    /// Runs program with `input` and returns its output and return code.
+    fn run(text: &str, input: &str) -> Result<(String, i64)> {
+        testing::run(compile(&[TextFile{name: "test".to_string(), text: text.to_string()}])?, input)
+    }
+
+    #[test]
+    fn compiles_recursive_functions() {
+        let text = "
+            int fact(int n) {
+                if (n <= 1) return 1;
+                return n * fact(n - 1);
+            }
+            int fib(int n) { if (n < 2) return n; return fib(n-1) + fib(n-2); }
+            int main() { return fact(5) - fib(10) * 2 + (7 % 4 == 3 && 1 != 2 || 0); }";
+
+        assert_eq!(run(text, "").unwrap(), (String::new(), 120 - 55*2 + 1));
+    }
+
+    #[test]
+    fn uses_global_and_local_arrays() {
+        // NOTE: reverses input line, prints sum of digits from local array
+        let text = "
+            int line[16];
+            int length;
+            void sum(int digits, int n) {
+                int total = 0;
+                while (n > 0) { n = n - 1; total = total + digits[n]; }
+                putchar('0' + total);
+            }
+            int main() {
+                int c = getchar();
+                int digits[3];
+                while (c != '\\n') { line[length] = c; length = length + 1; c = getchar(); }
+                while (length > 0) { length = length - 1; putchar(line[length]); }
+                digits[0] = 1; digits[1] = 3; digits[2] = digits[0] + digits[1];
+                sum(digits, 3);
+                return -length;
+            }";
+
+        assert_eq!(run(text, "abc\n").unwrap(), ("cba8".to_string(), 0));
+    }
+
+    #[test]
+    fn compiles_deeply_nested_expressions() {
+        let text = format!("int main() {{ return {}1{}; }}", "-(1+".repeat(30), "+1)".repeat(30));
+
+        assert_eq!(run(&text, "").unwrap().1, 1);
+    }
+
+    #[test]
+    fn frees_local_arrays_at_end_of_block() {
+        let text = "int main() { int i = 0; while (i < 2000) { int a[1000]; a[999] = i; i = i + 1; } return i - 2000; }";
+
+        assert_eq!(run(text, "").unwrap().1, 0);
+    }
+
+    #[test]
+    fn error_on_programs_exceeding_limits() {
+        assert_eq!(run("int main() { int a[9000000000000000000]; }", "").unwrap_err().to_string(),
+                   "test:1:20: array of 9000000000000000000 cells doesn't fit in VM memory");
+        assert_eq!(run("int a[500000]; int b[500000]; int main() {}", "").unwrap_err().to_string(),
+                   "test:1:20: global b doesn't fit in VM memory");
+        assert_eq!(run("int main() { int a[999744]; { int b[1]; } }", "").unwrap_err().to_string(),
+                   "test:1:35: local array b doesn't fit in VM memory");
+        let text = format!("int main() {{ return {}1; }}", "(".repeat(100000));
+        assert!(run(&text, "").unwrap_err().to_string().ends_with("expression nested deeper than 128 levels"));
+        let text = format!("int main() {{ return 1{}; }}", "+1".repeat(100000));
+        assert!(run(&text, "").unwrap_err().to_string().ends_with("expression nested deeper than 128 levels"));
+    }
+
+    #[test]
+    fn keeps_evaluation_stack_across_calls() {
+        let text = "
+            int g = 2;
+            int twice(int x) { g = g + 1; return x + x; }
+            int main() { int a; int b; a = b = 3; return 100 - twice(a) * (twice(b) - g); }";
+
+        assert_eq!(run(text, "").unwrap().1, 100 - 6 * (6 - 4));
+    }
+
+    #[test]
+    fn initializes_globals_with_negative_numbers() {
+        let text = "int low = -1; int high = 5; int main() { return high * low; }";
+
+        assert_eq!(run(text, "").unwrap().1, -5);
+        assert_eq!(run("int g = -x; int main() {}", "").unwrap_err().to_string(), "test:1:10: global can only be initialized with number");
+    }
+
+    #[test]
+    fn error_on_invalid_programs() {
+        assert_eq!(run("int main() { return x; }", "").unwrap_err().to_string(), "test:1:21: undefined variable: x");
+        assert_eq!(run("int f(int a) { return a; }\nint main() { f(); g(1); }", "").unwrap_err().to_string(),
+                   "test:2:14: function f expects 1 arguments, got 0\ntest:2:19: undefined function: g");
+        assert_eq!(run("int main() { int a; int a; 1 = a; }", "").unwrap_err().to_string(),
+                   "test:1:30: left side of assignment must be variable or array element");
+        assert_eq!(run("int main() { int a; int a; }", "").unwrap_err().to_string(),
+                   "test:1:25: variable declared twice in the same scope: a");
+        assert_eq!(run("int main() { while (1) { }", "").unwrap_err().to_string(), "test:1:27: expected }, got end of file");
+        assert_eq!(run("int f() {}", "").unwrap_err().to_string(), "no main function");
+    }
+}
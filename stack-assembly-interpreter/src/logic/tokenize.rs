@@ -1,55 +1,207 @@
-use anyhow::{anyhow, Result, Context, Error};
+use std::num::IntErrorKind;
+
+use anyhow::{anyhow, bail, Result};
 use once_cell::unsync::Lazy;
 use regex::Regex;
-use beau_collector::BeauCollector as _;
 
+use crate::models::error::{CollectErrors as _, error_at, SourceContext as _};
 use crate::models::token::Token;
 use crate::models::token::Position;
+use super::expression;
+
+fn failed_to_tokenize_message(token_type: &str, token_str: &str) -> String {
+    format!("failed to tokenize {token_type}: \"{token_str}\"")
+}
+
+/// Decodes text of string literal into code points with offsets of their source characters from the literal start.
+/// `c"..."` literals are NUL-terminated.
+pub fn decode_string(literal: &str) -> Result<Vec<(i64, usize)>> {
+    let (body, offset, nul_terminated) = match literal.strip_prefix('c') {
+        Some(rest) => (rest, 1, true),
+        None => (literal, 0, false),
+    };
+    let body = body.strip_prefix('"')
+                   .and_then(|x| x.strip_suffix('"'))
+                   .ok_or_else(|| anyhow!("string must be enclosed in double quotes"))?;
+    let mut res = unescape(body, '"')?.into_iter()
+                                      .map(|(c, i)| (c, i+offset+1))
+                                      .collect::<Vec<_>>();
+    if nul_terminated {
+        res.push((0, offset+1+body.chars().count()));
+    }
+    Ok(res)
+}
+
+pub fn string_value(literal: &str) -> Result<String> {
+    decode_string(literal)?.into_iter()
+                           .map(|(c, _)| char::from_u32(c as u32).ok_or_else(|| anyhow!("invalid character code {c}")))
+                           .collect()
+}
+
+pub fn decode_char(literal: &str) -> Result<i64> {
+    let body = literal.strip_prefix('\'')
+                      .and_then(|x| x.strip_suffix('\''))
+                      .ok_or_else(|| anyhow!("char must be enclosed in single quotes"))?;
+    match unescape(body, '\'')?.as_slice() {
+        [(c, _)] => Ok(*c),
+        _ => Err(anyhow!("char literal must contain exactly one character")),
+    }
+}
 
-fn failed_to_tokenize_error(token_type: &str, token_str: &str, pos: &Position) -> Error {
-    anyhow!("{pos}: failed to tokenize {token_type}: \"{token_str}\"")
+fn unescape(body: &str, quote: char) -> Result<Vec<(i64, usize)>> {
+    let mut res = vec![];
+    let mut chars = body.chars().enumerate();
+    while let Some((i, c)) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next().map(|(_, x)| x) {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(x @ ('\\' | '\'' | '"')) => x,
+                Some(x) => return Err(anyhow!("unknown escape sequence: \\{x}")),
+                None => return Err(anyhow!("unterminated escape sequence")),
+            },
+            x if x == quote => return Err(anyhow!("unescaped quote inside literal")),
+            x => x,
+        };
+        res.push((c as i64, i));
+    }
+    Ok(res)
+}
+
+/// Parses integer literal with optional sign, `0x`/`0b`/`0o` radix prefix and `_` digit separators.
+pub fn parse_integer(literal: &str) -> Result<i64> {
+    let (negative, unsigned) = match literal.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, literal.strip_prefix('+').unwrap_or(literal)),
+    };
+    let (radix, name, digits) = match unsigned.get(..2) {
+        Some("0x") => (16, "hexadecimal", &unsigned[2..]),
+        Some("0b") => (2, "binary", &unsigned[2..]),
+        Some("0o") => (8, "octal", &unsigned[2..]),
+        _ => (10, "decimal", unsigned),
+    };
+    let digits = digits.replace('_', "");
+    if digits.is_empty() {
+        bail!("{name} literal has no digits")
+    }
+    let magnitude = u64::from_str_radix(&digits, radix).map_err(|err| match err.kind() {
+        IntErrorKind::PosOverflow => anyhow!("{name} literal out of range for i64"),
+        _ => anyhow!("invalid digit in {name} literal"),
+    })?;
+    let value = if negative { -i128::from(magnitude) } else { i128::from(magnitude) };
+    i64::try_from(value).map_err(|_| anyhow!("{name} literal out of range for i64"))
+}
+
+/// Tokens with operators or parentheses after the first symbol are expressions, e.g. `Buffer+3` or `(End-Start)`.
+/// `-` is an ident character, so subtraction from a symbol must be parenthesized: `(Buffer-3)`.
+fn is_expression(token_str: &str) -> bool {
+    token_str.starts_with('(') || token_str.chars().skip(1).any(|c| "+*/%()".contains(c))
 }
 
 fn get_token(token_str: &str, pos: Position) -> Result<Token> {
-    let ident_re = Lazy::new(|| Regex::new(r"^[[:alpha:]_][[:word:]-]*$").unwrap());
-    let declaration_re = Lazy::new(|| Regex::new(r"^:[[:alpha:]_][[:word:]-]*$").unwrap());
+    let ident_re = Lazy::new(|| Regex::new(r"^\.?[[:alpha:]_][[:word:]-]*$").unwrap());
+    let declaration_re = Lazy::new(|| Regex::new(r"^:\.?[[:alpha:]_][[:word:]-]*$").unwrap());
+    let directive_re = Lazy::new(|| Regex::new(r"^%[[:alpha:]_][[:word:]-]*$").unwrap());
+    let ident_minus_digit_re = Lazy::new(|| Regex::new(r"-[[:digit:]]").unwrap());
 
     match token_str.chars().next() {
-        Some('a'..='z' | 'A'..='Z' | '_') => ident_re.is_match(token_str)
+        Some('"' | 'c') if token_str.starts_with('"') || token_str.starts_with("c\"") =>
+            decode_string(token_str).map(|_| Token::String(token_str.to_string(), pos.clone()))
+                                    .at_position(&pos, || failed_to_tokenize_message("string", token_str)),
+        Some('\'') => decode_char(token_str).map(|c| Token::Integer(c, pos.clone()))
+                                            .at_position(&pos, || failed_to_tokenize_message("char", token_str)),
+        Some('a'..='z' | 'A'..='Z' | '_' | '.' | '0'..='9' | '+' | '-' | '(') if is_expression(token_str) =>
+            expression::parse(token_str).map(|i| Token::Expression(i, pos.clone()))
+                                        .at_position(&pos, || failed_to_tokenize_message("expression", token_str)),
+        Some('a'..='z' | 'A'..='Z' | '_' | '.') if ident_minus_digit_re.is_match(token_str) =>
+            Err(error_at!(pos, "ambiguous ident: \"{token_str}\": write \"({token_str})\" to subtract")),
+        Some('a'..='z' | 'A'..='Z' | '_' | '.') => ident_re.is_match(token_str)
                                                      .then_some(Token::Ident(token_str.to_string(), pos.clone()) )
-                                                     .ok_or_else(|| failed_to_tokenize_error("ident", token_str, &pos)),
-        Some('0'..='9' | '+' | '-') => token_str.parse::<i64>().map(|i| Token::Integer(i, pos.clone()))
-                                                               .with_context(|| failed_to_tokenize_error("integer", token_str, &pos)),
+                                                     .ok_or_else(|| error_at!(pos, "{}", failed_to_tokenize_message("ident", token_str))),
+        Some('0'..='9' | '+' | '-') => parse_integer(token_str).map(|i| Token::Integer(i, pos.clone()))
+                                                               .at_position(&pos, || failed_to_tokenize_message("integer", token_str)),
         Some(':') => declaration_re.is_match(token_str)
                                    .then_some(Token::Declaration(token_str[1..].to_string(), pos.clone()))
-                                   .ok_or_else(|| failed_to_tokenize_error("declaration", token_str, &pos)),
-        Some(x) => Err(anyhow!("{pos}: token starts with illegal symbol: \"{x}\"")),
-        None => Err(anyhow!("{pos}: no valid symbol"))
+                                   .ok_or_else(|| error_at!(pos, "{}", failed_to_tokenize_message("declaration", token_str))),
+        Some('%') => directive_re.is_match(token_str)
+                                 .then_some(Token::Directive(token_str[1..].to_string(), pos.clone()))
+                                 .ok_or_else(|| error_at!(pos, "{}", failed_to_tokenize_message("directive", token_str))),
+        Some(x) => Err(error_at!(pos, "token starts with illegal symbol: \"{x}\"")),
+        None => Err(error_at!(pos, "no valid symbol"))
     }
 }
 
+/// Splits line into tokens with their columns and trailing comment starting with `;`.
+/// Whitespace and `;` inside quoted literals don't split tokens, neither does whitespace inside parentheses.
+pub fn split_line(line: &str) -> (Vec<(usize, &str)>, Option<&str>) {
+    let mut res = vec![];
+    let mut start: Option<usize> = None;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut depth: usize = 0;
+    let mut end = line.len();
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None => match c {
+                ';' => {
+                    end = i;
+                    break;
+                },
+                ' ' | '\t' if depth == 0 => if let Some(start) = start.take() {
+                    res.push((start, &line[start..i]));
+                },
+                ' ' | '\t' => {},
+                _ => {
+                    match c {
+                        '"' | '\'' => quote = Some(c),
+                        '(' => depth += 1,
+                        ')' => depth = depth.saturating_sub(1),
+                        _ => {},
+                    }
+                    start.get_or_insert(i);
+                },
+            },
+        }
+    }
+    if let Some(start) = start {
+        res.push((start, &line[start..end]));
+    }
+    let tokens = res.into_iter()
+                    .map(|(start, token)| (line[..start].chars().count()+1, token))
+                    .collect();
+    (tokens, line.get(end..).filter(|x| !x.is_empty()))
+}
+
 pub fn tokenize(text: &str, filename: &str) -> Result<Vec<Token>> {
-    let lines = text.lines().filter_map(|x| x.split(';').next()).enumerate();
-    let lines = lines.map(|x| {
-        let (i, line) = x;
-        (i+1, line.split(&[' ', '\t']))
-    });
+    let attribute_re = Lazy::new(|| Regex::new(r"^[[:alpha:]_][[:word:]-]*=[[:word:]-]+$").unwrap());
 
     let mut res: Vec<Result<Token>> = vec![];
-    for line in lines {
-        let (i, tokens) = line;
-        let mut column: usize = 1;
-        for token in tokens {
-            if token.is_empty() {
-                column += 1;
-                continue;
-            }
-            res.push(get_token(token, Position{filename: filename.to_string(), line: i, column}));
-            column += token.len()+1;
+    for (i, line) in text.lines().enumerate() {
+        // NOTE: `key=value` attributes are allowed only in `%proc` header, they're parsed by `procedure::expand`
+        let mut header = false;
+        for (column, token) in split_line(line).0 {
+            let pos = Position{filename: filename.to_string(), line: i+1, column, expansion: None};
+            header |= token == "%proc";
+            res.push(match header && attribute_re.is_match(token) {
+                true => Ok(Token::Ident(token.to_string(), pos)),
+                false => get_token(token, pos),
+            });
         }
     }
 
-    res.into_iter().bcollect()
+    res.into_iter().collect_errors()
+}
+
+/// Appends generated stack assembly `text` with all tokens at `pos`.
+pub fn emit(out: &mut Vec<Token>, text: &str, pos: &Position) -> Result<()> {
+    out.extend(tokenize(text, "")?.into_iter().map(|x| x.with_position(pos.clone())));
+    Ok(())
 }
 
 #[cfg(test)]
@@ -67,33 +219,88 @@ _Loop :a1 HALT _Read_number_ _- _ a1 ; a1 == 260
         let got = tokenize(text, "test");
 
         assert_eq!(got.unwrap(), vec![
-            Token::Integer(10, Position{filename: "test".to_string(), line: 1, column: 1}),
-            Token::Integer(65, Position{filename: "test".to_string(), line: 1, column: 4}),
-            Token::Integer(-40, Position{filename: "test".to_string(), line: 1, column: 8}),
-            Token::Declaration("_".to_string(), Position{filename: "test".to_string(), line: 1, column: 12}),
-            Token::Ident("_Loop".to_string(), Position{filename: "test".to_string(), line: 2, column: 1}),
-            Token::Declaration("a1".to_string(), Position{filename: "test".to_string(), line: 2, column: 7}),
-            Token::Ident("HALT".to_string(), Position{filename: "test".to_string(), line: 2, column: 11}),
-            Token::Ident("_Read_number_".to_string(), Position{filename: "test".to_string(), line: 2, column: 16}),
-            Token::Ident("_-".to_string(), Position{filename: "test".to_string(), line: 2, column: 30}),
-            Token::Ident("_".to_string(), Position{filename: "test".to_string(), line: 2, column: 33}),
-            Token::Ident("a1".to_string(), Position{filename: "test".to_string(), line: 2, column: 35}),
-            Token::Integer(123, Position{filename: "test".to_string(), line: 3, column: 1}),
-            Token::Integer(1234, Position{filename: "test".to_string(), line: 4, column: 1}),
-            Token::Ident("PROGRAM_SIZE".to_string(), Position{filename: "test".to_string(), line: 4, column: 6}),
-            Token::Declaration("_Loop".to_string(), Position{filename: "test".to_string(), line: 5, column: 1}),
-            Token::Declaration("_Read_number_".to_string(), Position{filename: "test".to_string(), line: 5, column: 8}),
-            Token::Declaration("_-".to_string(), Position{filename: "test".to_string(), line: 5, column: 23}),
+            Token::Integer(10, Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Integer(65, Position{filename: "test".to_string(), line: 1, column: 4, expansion: None}),
+            Token::Integer(-40, Position{filename: "test".to_string(), line: 1, column: 8, expansion: None}),
+            Token::Declaration("_".to_string(), Position{filename: "test".to_string(), line: 1, column: 12, expansion: None}),
+            Token::Ident("_Loop".to_string(), Position{filename: "test".to_string(), line: 2, column: 1, expansion: None}),
+            Token::Declaration("a1".to_string(), Position{filename: "test".to_string(), line: 2, column: 7, expansion: None}),
+            Token::Ident("HALT".to_string(), Position{filename: "test".to_string(), line: 2, column: 11, expansion: None}),
+            Token::Ident("_Read_number_".to_string(), Position{filename: "test".to_string(), line: 2, column: 16, expansion: None}),
+            Token::Ident("_-".to_string(), Position{filename: "test".to_string(), line: 2, column: 30, expansion: None}),
+            Token::Ident("_".to_string(), Position{filename: "test".to_string(), line: 2, column: 33, expansion: None}),
+            Token::Ident("a1".to_string(), Position{filename: "test".to_string(), line: 2, column: 35, expansion: None}),
+            Token::Integer(123, Position{filename: "test".to_string(), line: 3, column: 1, expansion: None}),
+            Token::Integer(1234, Position{filename: "test".to_string(), line: 4, column: 1, expansion: None}),
+            Token::Ident("PROGRAM_SIZE".to_string(), Position{filename: "test".to_string(), line: 4, column: 6, expansion: None}),
+            Token::Declaration("_Loop".to_string(), Position{filename: "test".to_string(), line: 5, column: 1, expansion: None}),
+            Token::Declaration("_Read_number_".to_string(), Position{filename: "test".to_string(), line: 5, column: 8, expansion: None}),
+            Token::Declaration("_-".to_string(), Position{filename: "test".to_string(), line: 5, column: 23, expansion: None}),
         ]);
     }
 
     #[test]
-    fn tokenize_error_unknown_symbol_in_ident() {
+    fn tokenizes_sum_of_symbols_as_expression() {
         let text = "PROGRAM+SIZE";
 
         let got = tokenize(text, "test");
 
-        assert_eq!(got.unwrap_err().to_string(), "test:1:1: failed to tokenize ident: \"PROGRAM+SIZE\"");
+        assert_eq!(got.unwrap(), vec![
+            Token::Expression(expression::parse("PROGRAM+SIZE").unwrap(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+        ]);
+    }
+
+    #[test]
+    fn tokenize_error_unknown_symbol_in_ident() {
+        let text = "PROGRAM$SIZE";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:1: failed to tokenize ident: \"PROGRAM$SIZE\"");
+    }
+
+    #[test]
+    fn tokenizes_expressions() {
+        let text = "PROGRAM_SIZE+1 ( End - Start ) (Size-1)";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap(), vec![
+            Token::Expression(expression::parse("PROGRAM_SIZE+1").unwrap(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Expression(expression::parse("End-Start").unwrap(), Position{filename: "test".to_string(), line: 1, column: 16, expansion: None}),
+            Token::Expression(expression::parse("Size-1").unwrap(), Position{filename: "test".to_string(), line: 1, column: 32, expansion: None}),
+        ]);
+    }
+
+    #[test]
+    fn tokenize_error_ambiguous_subtraction_in_ident() {
+        let text = "1 Buffer-3";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:3: ambiguous ident: \"Buffer-3\": write \"(Buffer-3)\" to subtract");
+    }
+
+    #[test]
+    fn tokenizes_local_labels() {
+        let text = ":.loop .loop .loop+1";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap(), vec![
+            Token::Declaration(".loop".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident(".loop".to_string(), Position{filename: "test".to_string(), line: 1, column: 8, expansion: None}),
+            Token::Expression(expression::parse(".loop+1").unwrap(), Position{filename: "test".to_string(), line: 1, column: 14, expansion: None}),
+        ]);
+    }
+
+    #[test]
+    fn tokenize_error_ill_formed_expression() {
+        let text = "(1+2";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:1: failed to tokenize expression: \"(1+2\": expected closing parenthesis");
     }
 
     #[test]
@@ -102,7 +309,42 @@ _Loop :a1 HALT _Read_number_ _- _ a1 ; a1 == 260
 
         let got = tokenize(text, "test");
 
-        assert_eq!(got.unwrap_err().to_string(), "test:1:5: failed to tokenize integer: \"99999999999999999999\": number too large to fit in target type");
+        assert_eq!(got.unwrap_err().to_string(), "test:1:5: failed to tokenize integer: \"99999999999999999999\": decimal literal out of range for i64");
+    }
+
+    #[test]
+    fn tokenizes_integers_in_different_radixes() {
+        let text = "0x1F -0b1010 +0o17 1_000_000 0xFF_FF -0x8000_0000_0000_0000";
+
+        let got = tokenize(text, "test").unwrap()
+                                        .into_iter()
+                                        .map(|x| match x {
+                                            Token::Integer(i, _) => i,
+                                            _ => panic!("unexpected token {x:?}"),
+                                        })
+                                        .collect::<Vec<_>>();
+
+        assert_eq!(got, vec![31, -10, 15, 1000000, 65535, i64::MIN]);
+    }
+
+    #[test]
+    fn tokenize_error_integer_out_of_range_names_radix() {
+        let text = "0x8000000000000000 0b1_0000000000000000000000000000000000000000000000000000000000000000";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:1: failed to tokenize integer: \"0x8000000000000000\": hexadecimal literal out of range for i64
+test:1:20: failed to tokenize integer: \"0b1_0000000000000000000000000000000000000000000000000000000000000000\": binary literal out of range for i64");
+    }
+
+    #[test]
+    fn tokenize_error_invalid_digit() {
+        let text = "0b102 0x";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:1: failed to tokenize integer: \"0b102\": invalid digit in binary literal
+test:1:7: failed to tokenize integer: \"0x\": hexadecimal literal has no digits");
     }
 
     #[test]
@@ -123,4 +365,105 @@ _Loop :a1 HALT _Read_number_ _- _ a1 ; a1 == 260
         assert_eq!(got.unwrap_err().to_string(), "test:1:5: failed to tokenize declaration: \":123\"");
     }
 
+    #[test]
+    fn tokenizes_directives() {
+        let text = "%macro PUSH2 a b\n%endm";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap(), vec![
+            Token::Directive("macro".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("PUSH2".to_string(), Position{filename: "test".to_string(), line: 1, column: 8, expansion: None}),
+            Token::Ident("a".to_string(), Position{filename: "test".to_string(), line: 1, column: 14, expansion: None}),
+            Token::Ident("b".to_string(), Position{filename: "test".to_string(), line: 1, column: 16, expansion: None}),
+            Token::Directive("endm".to_string(), Position{filename: "test".to_string(), line: 2, column: 1, expansion: None}),
+        ]);
+    }
+
+    #[test]
+    fn tokenizes_attributes_only_in_proc_header() {
+        let got = tokenize("%proc Add args=2", "test");
+
+        assert_eq!(got.unwrap(), vec![
+            Token::Directive("proc".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Ident("Add".to_string(), Position{filename: "test".to_string(), line: 1, column: 7, expansion: None}),
+            Token::Ident("args=2".to_string(), Position{filename: "test".to_string(), line: 1, column: 11, expansion: None}),
+        ]);
+        assert_eq!(tokenize("Size=2", "test").unwrap_err().to_string(), "test:1:1: failed to tokenize ident: \"Size=2\"");
+    }
+
+    #[test]
+    fn tokenizes_strings() {
+        let text = "%include \"lib/io.asm\"";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap(), vec![
+            Token::Directive("include".to_string(), Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::String("\"lib/io.asm\"".to_string(), Position{filename: "test".to_string(), line: 1, column: 10, expansion: None}),
+        ]);
+    }
+
+    #[test]
+    fn tokenizes_char_literals() {
+        let text = "'A' '\\n'\t'\\'' ';' ; ';'";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap(), vec![
+            Token::Integer(65, Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::Integer(10, Position{filename: "test".to_string(), line: 1, column: 5, expansion: None}),
+            Token::Integer(39, Position{filename: "test".to_string(), line: 1, column: 10, expansion: None}),
+            Token::Integer(59, Position{filename: "test".to_string(), line: 1, column: 15, expansion: None}),
+        ]);
+    }
+
+    #[test]
+    fn tokenizes_string_with_spaces_and_semicolons() {
+        let text = "1 \"a; \\\"b\\\"\" c\"ok\" 2 ; \"comment\"";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap(), vec![
+            Token::Integer(1, Position{filename: "test".to_string(), line: 1, column: 1, expansion: None}),
+            Token::String("\"a; \\\"b\\\"\"".to_string(), Position{filename: "test".to_string(), line: 1, column: 3, expansion: None}),
+            Token::String("c\"ok\"".to_string(), Position{filename: "test".to_string(), line: 1, column: 14, expansion: None}),
+            Token::Integer(2, Position{filename: "test".to_string(), line: 1, column: 20, expansion: None}),
+        ]);
+    }
+
+    #[test]
+    fn decodes_strings() {
+        assert_eq!(decode_string("\"a\\tb\"").unwrap(), vec![(97, 1), (9, 2), (98, 4)]);
+        assert_eq!(decode_string("c\"hi\"").unwrap(), vec![(104, 2), (105, 3), (0, 4)]);
+    }
+
+    #[test]
+    fn tokenize_error_unterminated_string() {
+        let text = "1 \"abc ; def";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:3: failed to tokenize string: \"\"abc ; def\": string must be enclosed in double quotes");
+    }
+
+    #[test]
+    fn tokenize_error_bad_char_literal() {
+        let text = "'ab' '\\q'";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:1: failed to tokenize char: \"'ab'\": char literal must contain exactly one character
+test:1:6: failed to tokenize char: \"'\\q'\": unknown escape sequence: \\q");
+    }
+
+    #[test]
+    fn tokenize_error_ill_formed_directive() {
+        let text = "%1rep";
+
+        let got = tokenize(text, "test");
+
+        assert_eq!(got.unwrap_err().to_string(), "test:1:1: failed to tokenize directive: \"%1rep\"");
+    }
+
 }
@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+
+use crate::models::error::{CollectErrors as _, error_at};
+use crate::models::expression::Expression;
+use crate::models::token::{Position, Token};
+use super::labels;
+
+/// Returns symbol name as written in source, without module prefix of private symbol.
+pub fn source_name(name: &str) -> &str {
+    name.rsplit("::").next().unwrap()
+}
+
+/// Whether symbol was renamed as private to its module.
+pub fn is_private(name: &str) -> bool {
+    name.contains("::")
+}
+
+/// Returns expression as written in source, see [`source_name`].
+pub fn source_expression(expression: &Expression) -> String {
+    let mut expression = expression.clone();
+    for name in expression.symbols_mut() {
+        *name = source_name(name).to_string();
+    }
+    expression.to_string()
+}
+
+struct Symbols<'a> {
+    module: &'a str,
+    declared: HashSet<String>,
+    globals: Vec<(String, Position)>,
+    externs: Vec<(String, Position)>,
+}
+
+fn contains(symbols: &[(String, Position)], name: &str) -> bool {
+    symbols.iter().any(|(x, _)| x == name)
+}
+
+impl Symbols<'_> {
+    fn resolve(&self, name: &str) -> Option<String> {
+        if self.declared.contains(name) {
+            if contains(&self.globals, name) || labels::is_builtin(name) {
+                return Some(name.to_string())
+            }
+            return Some(format!("{}::{name}", self.module))
+        }
+        (contains(&self.externs, name) || labels::is_builtin(name)).then(|| name.to_string())
+    }
+}
+
+type Directives = Vec<(String, Position)>;
+
+/// Removes `%global`/`%extern` directives, collecting names listed on the same line.
+fn collect_directives(tokens: Vec<Token>) -> Result<(Vec<Token>, Directives, Directives)> {
+    let mut globals = vec![];
+    let mut externs = vec![];
+    let mut res = vec![];
+    let mut errors: Vec<Result<()>> = vec![];
+    let mut directive: Option<(String, Position)> = None;
+    for token in tokens {
+        match (&directive, token) {
+            (_, Token::Directive(name, pos)) if name == "global" || name == "extern" => directive = Some((name, pos)),
+            (Some((name, pos)), Token::Ident(symbol, symbol_pos)) if symbol_pos.same_line(pos) => {
+                if name == "global" {
+                    globals.push((symbol, symbol_pos));
+                } else {
+                    externs.push((symbol, symbol_pos));
+                }
+            },
+            (Some((name, pos)), token) if token.position().same_line(pos) => {
+                errors.push(Err(error_at!(token.position(), "expected symbol name after %{name}")));
+            },
+            (_, token) => {
+                directive = None;
+                res.push(token);
+            },
+        }
+    }
+    errors.into_iter().collect_errors::<()>()?;
+    Ok((res, globals, externs))
+}
+
+/// Makes symbols declared in `module` private, except ones exported with `%global`.
+/// Symbols of other modules are available only after `%extern`.
+/// Private symbols are renamed to `module::name`.
+pub fn resolve(tokens: Vec<Token>, module: &str) -> Result<Vec<Token>> {
+    let (tokens, globals, externs) = collect_directives(tokens)?;
+    let declared: HashSet<String> = tokens.iter()
+                                          .filter_map(|x| match x {
+                                              Token::Declaration(name, _) | Token::Constant(name, _, _) => Some(name.clone()),
+                                              _ => None,
+                                          })
+                                          .collect();
+    let symbols = Symbols{module, declared, globals, externs};
+
+    let mut errors: Vec<Result<()>> = vec![];
+    for (name, pos) in &symbols.globals {
+        if !symbols.declared.contains(name) {
+            errors.push(Err(error_at!(pos, "global symbol is not declared in this file: {name}")));
+        }
+    }
+    for (name, pos) in &symbols.externs {
+        if symbols.declared.contains(name) {
+            errors.push(Err(error_at!(pos, "extern symbol is declared in this file: {name}")));
+        }
+    }
+    errors.into_iter().collect_errors::<()>()?;
+
+    tokens.into_iter()
+          .map(|mut token| -> Result<Token> {
+              match &mut token {
+                  Token::Declaration(name, _) | Token::Constant(name, _, _) => *name = symbols.resolve(name).unwrap(),
+                  Token::Ident(name, pos) => *name = symbols.resolve(name)
+                                                            .ok_or_else(|| error_at!(pos, "undefined ident: \"{name}\""))?,
+                  Token::Expression(expression, pos) => for name in expression.symbols_mut() {
+                      *name = symbols.resolve(name)
+                                     .ok_or_else(|| error_at!(pos, "undefined symbol in expression: \"{name}\""))?;
+                  },
+                  _ => {},
+              }
+              Ok(token)
+          })
+          .collect_errors()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::tokenize::tokenize;
+
+    fn names(tokens: Vec<Token>) -> Vec<String> {
+        tokens.into_iter()
+              .map(|x| match x {
+                  Token::Declaration(i, _) => format!(":{i}"),
+                  Token::Ident(i, _) => i,
+                  Token::Constant(i, value, _) => format!("{i}={value}"),
+                  Token::Expression(i, _) => i.to_string(),
+                  Token::Integer(i, _) => i.to_string(),
+                  _ => panic!("unexpected token {x:?}"),
+              })
+              .collect()
+    }
+
+    #[test]
+    fn renames_private_symbols() {
+        let tokens = tokenize("%global Main Size
+%extern Print
+:Main Loop Print JMP :Loop Size EQU 1 Step EQU 2 Loop+Step PROGRAM_SIZE", "a.asm").unwrap();
+
+        let got = resolve(labels::fold_constants(tokens).unwrap(), "a.asm");
+
+        assert_eq!(names(got.unwrap()), vec![
+            ":Main", "a.asm::Loop", "Print", "JMP", ":a.asm::Loop", "Size=1", "a.asm::Step=2", "(a.asm::Loop+a.asm::Step)", "PROGRAM_SIZE",
+        ]);
+    }
+
+    #[test]
+    fn error_on_reference_without_extern() {
+        let tokens = tokenize("Print JMP Print+1", "a.asm").unwrap();
+
+        let got = resolve(tokens, "a.asm");
+
+        assert_eq!(got.unwrap_err().to_string(), "a.asm:1:1: undefined ident: \"Print\"
+a.asm:1:11: undefined symbol in expression: \"Print\"");
+    }
+
+    #[test]
+    fn error_on_invalid_directives() {
+        let tokens = tokenize("%extern Print :Loop\n%global 1", "a.asm").unwrap();
+
+        let got = resolve(tokens, "a.asm");
+
+        assert_eq!(got.unwrap_err().to_string(), "a.asm:1:15: expected symbol name after %extern
+a.asm:2:9: expected symbol name after %global");
+    }
+
+    #[test]
+    fn error_on_undeclared_global_and_declared_extern() {
+        let tokens = tokenize("%global Main\n%extern Loop\n:Loop", "a.asm").unwrap();
+
+        let got = resolve(tokens, "a.asm");
+
+        assert_eq!(got.unwrap_err().to_string(), "a.asm:1:9: global symbol is not declared in this file: Main
+a.asm:2:9: extern symbol is declared in this file: Loop");
+    }
+}
@@ -1,10 +1,12 @@
-use anyhow::{anyhow, Context, Result, Error};
+use anyhow::Result;
 
+use crate::models::error::SourceError;
 use crate::models::token::Token;
 use crate::models::vm::VM;
-use crate::models::command::{Input, Instruction, Output, ReturnCode};
+use crate::models::command::{Input, Output, ReturnCode};
 
 use super::command::get_handler;
+use super::visibility::{source_expression, source_name};
 
 // NOTE: it's easier here to use a crate that can create mock of struct
 pub struct Executor<'a, IO: Input + Output> {
@@ -22,24 +24,32 @@ impl<'a, IO: Input + Output> Executor<'a, IO> {
 
     fn execute_step(&mut self, vm: &mut VM) -> Result<Option<ReturnCode>> {
         let ip = vm.registers().ip;
-        let instruction = vm.read_code(ip)?.clone();
-        let opcode = instruction.opcode;
+        let opcode = vm.read_code(ip)?.opcode;
         vm.registers_mut().ip += 1;
-        (|| match opcode {
-            0.. => {
-                vm.push(opcode)?;
-                Ok(None)
-            },
-            ..=-1 => get_handler(opcode)?.handle(vm, self.io)
-        })().context(get_failed_to_execute_error(&instruction))
+        let res = match opcode {
+            0.. => vm.push(opcode).map(|_| None),
+            ..=-1 => get_handler(opcode).and_then(|handler| handler.handle(vm, self.io)),
+        };
+        // NOTE: error is built only on failure, as this is the hot loop
+        res.map_err(|err| match vm.read_code(ip) {
+            Ok(instruction) => SourceError::new(instruction.token.position().clone(), failed_to_execute_message(&instruction.token))
+                                   .with_note(&format!("at address {ip}"))
+                                   .caused_by(err)
+                                   .into(),
+            Err(err) => err,
+        })
     }
 }
 
-fn get_failed_to_execute_error(instruction: &Instruction) -> Error {
-    match &instruction.token {
-        Token::Integer(i, pos) => anyhow!("{pos}: failed to execute integer instruction {i}"),
-        Token::Declaration(i, pos) => anyhow!("{pos}: can't execute declaration {i}"),
-        Token::Ident(i, pos) => anyhow!("{pos}: failed to execute ident instruction {i}"),
+fn failed_to_execute_message(token: &Token) -> String {
+    match token {
+        Token::Integer(i, _) => format!("failed to execute integer instruction {i}"),
+        Token::Declaration(i, _) => format!("can't execute declaration {}", source_name(i)),
+        Token::Ident(i, _) => format!("failed to execute ident instruction {}", source_name(i)),
+        Token::Directive(i, _) => format!("can't execute directive %{i}"),
+        Token::String(i, _) => format!("can't execute string \"{i}\""),
+        Token::Constant(i, _, _) => format!("can't execute constant definition {}", source_name(i)),
+        Token::Expression(i, _) => format!("failed to execute expression instruction {}", source_expression(i)),
     }
 }
 
@@ -47,14 +57,14 @@ fn get_failed_to_execute_error(instruction: &Instruction) -> Error {
 mod tests {
     use mockall::{mock, predicate};
 
-    use crate::logic::{assembly::{self, TextFile}, stdio::Stdio};
+    use crate::logic::{assembly::{self, Options, TextFile}, stdio::Stdio};
 
     use super::*;
 
     #[test]
     fn halt_returns_error_code() {
         let files = &[TextFile{name: "stdin".to_string(), text: "2 3 ADD HALT".to_string()}];
-        let instructions = assembly::assembly(files).unwrap();
+        let instructions = assembly::assembly(files, &Options::default()).unwrap();
         let vm = VM::new(instructions);
         let mut io = Stdio::new();
         let mut executor = Executor{io: &mut io};
@@ -66,7 +76,7 @@ mod tests {
     #[test]
     fn executes_simple_program() {
         let files = &[TextFile{name: "stdin".to_string(), text: "2 3 ADD 0 HALT".to_string()}];
-        let instructions = assembly::assembly(files).unwrap();
+        let instructions = assembly::assembly(files, &Options::default()).unwrap();
         let vm = VM::new(instructions);
         let mut io = Stdio::new();
         let mut executor = Executor{io: &mut io};
@@ -79,7 +89,7 @@ mod tests {
     #[test]
     fn halt_on_empty_stack_fails() {
         let files = &[TextFile{name: "stdin".to_string(), text: "HALT".to_string()}];
-        let instructions = assembly::assembly(files).unwrap();
+        let instructions = assembly::assembly(files, &Options::default()).unwrap();
         let vm = VM::new(instructions);
         let mut io = Stdio::new();
         let mut executor = Executor{io: &mut io};
@@ -89,6 +99,19 @@ mod tests {
         assert_eq!(got.unwrap_err().to_string(), "stdin:1:1: failed to execute ident instruction HALT")
     }
 
+    #[test]
+    fn failed_instruction_error_notes_address() {
+        let files = &[TextFile{name: "stdin".to_string(), text: "1 ADD".to_string()}];
+        let instructions = assembly::assembly(files, &Options::default()).unwrap();
+        let vm = VM::new(instructions);
+        let mut io = Stdio::new();
+        let mut executor = Executor{io: &mut io};
+
+        let got = executor.execute(vm).unwrap_err();
+
+        assert_eq!(SourceError::of(&got).unwrap().notes, ["at address 257"]);
+    }
+
     mock! {
         InputOutput {}
         impl Input for InputOutput {
@@ -102,7 +125,7 @@ mod tests {
     #[test]
     fn out_instruction_outputs_symbol() {
         let files = &[TextFile{name: "stdin".to_string(), text: "2 3 ADD OUT 0 HALT".to_string()}];
-        let instructions = assembly::assembly(files).unwrap();
+        let instructions = assembly::assembly(files, &Options::default()).unwrap();
         let vm = VM::new(instructions);
         let mut io = MockInputOutput::new();
 
@@ -1,17 +1,145 @@
 use anyhow::{anyhow, Result};
 
-use std::{env, process::ExitCode};
+use std::{env, io::{stderr, IsTerminal}, process::ExitCode};
 
-use stack_assembly_interpreter::run;
+use stack_assembly_interpreter::{assemble_objects, build, check_stack, describe_lints, disassemble, exec, format_files, link, parse_lint_option, render_error, run, run_brainfuck, run_forth, run_tinyc, Options};
 
-fn main() -> Result<ExitCode> {
-    let args: Vec<String> = env::args().collect();
-    let file_paths = &args[1..];
+enum Mode {
+    Run,
+    Assemble,
+    Link,
+    Build,
+    Exec,
+    Disassemble,
+    Format,
+    Check,
+    Forth,
+    TinyC,
+    Brainfuck,
+}
+
+/// Mode is first argument, so files named like modes must be passed after `--`.
+const USAGE: &str = "usage: stack-assembly-interpreter [MODE] [OPTIONS] [--] FILES...";
+
+/// Colors are used when stderr is a terminal, unless `NO_COLOR` is set.
+fn auto_color() -> bool {
+    stderr().is_terminal() && env::var_os("NO_COLOR").is_none()
+}
+
+/// Parses `NAME=value` or `NAME`, which is defined as 1.
+fn parse_define(definition: &str) -> Result<(String, i64)> {
+    match definition.split_once('=') {
+        Some((name, value)) => Ok((name.to_string(), value.parse().map_err(|_| anyhow!("invalid value of {name}: {value}"))?)),
+        None => Ok((definition.to_string(), 1)),
+    }
+}
+
+fn main() -> ExitCode {
+    let mut options = Options{color: auto_color(), ..Options::default()};
+    match run_cli(&mut options) {
+        Ok(rc) => rc,
+        Err(err) => {
+            eprint!("{}", render_error(&err, options.color));
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn run_cli(options: &mut Options) -> Result<ExitCode> {
+    let mut file_paths = vec![];
+    let mut output = None;
+    let mut debug_info = true;
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().is_some_and(|x| x == "lints") {
+        print!("{}", describe_lints());
+        return Ok(ExitCode::SUCCESS)
+    }
+    let mode = match args.peek().map(String::as_str) {
+        Some("asm") => Mode::Assemble,
+        Some("link") => Mode::Link,
+        Some("build") => Mode::Build,
+        Some("exec") => Mode::Exec,
+        Some("disasm") => Mode::Disassemble,
+        Some("fmt") => Mode::Format,
+        Some("check") => Mode::Check,
+        Some("forth") => Mode::Forth,
+        Some("tinyc") => Mode::TinyC,
+        Some("bf") => Mode::Brainfuck,
+        _ => Mode::Run,
+    };
+    if !matches!(mode, Mode::Run) {
+        args.next();
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-I" => options.include_dirs.push(args.next().ok_or_else(|| anyhow!("expected directory after -I"))?.into()),
+            _ if arg.starts_with("-I") => options.include_dirs.push(arg[2..].into()),
+            "-o" => output = Some(args.next().ok_or_else(|| anyhow!("expected file path after -o"))?),
+            "-D" => options.defines.push(parse_define(&args.next().ok_or_else(|| anyhow!("expected definition after -D"))?)?),
+            _ if arg.starts_with("-D") => options.defines.push(parse_define(&arg[2..])?),
+            "--listing" => options.listing = Some(args.next().ok_or_else(|| anyhow!("expected file path after --listing"))?.into()),
+            "--symbols" => options.symbol_map = Some(args.next().ok_or_else(|| anyhow!("expected file path after --symbols"))?.into()),
+            "--allow" | "--warn" | "--deny" => {
+                let name = args.next().ok_or_else(|| anyhow!("expected lint name after {arg}"))?;
+                options.lint_levels.push(parse_lint_option(&arg, &name)?);
+            },
+            "--color" => options.color = match args.next().as_deref() {
+                Some("always") => true,
+                Some("never") => false,
+                Some("auto") => auto_color(),
+                _ => return Err(anyhow!("expected always, never or auto after --color")),
+            },
+            "--no-debug" => debug_info = false,
+            "--" => file_paths.extend(args.by_ref()),
+            _ if arg.starts_with('-') => return Err(anyhow!("unknown option: {arg}\n{USAGE}")),
+            _ => file_paths.push(arg),
+        }
+    }
+
+    if output.is_some() && !matches!(mode, Mode::Assemble | Mode::Link | Mode::Build) {
+        return Err(anyhow!("option -o is supported only in asm, link and build modes"))
+    }
+    let image_path = output.as_deref().unwrap_or("a.img");
 
     if file_paths.is_empty() {
         return Err(anyhow!("no source files provided"))
     }
 
-    let rc = run(&file_paths)?;
+    let rc = match mode {
+        Mode::Run => run(&file_paths, options)?,
+        Mode::Assemble => {
+            assemble_objects(&file_paths, options, output.as_deref())?;
+            0
+        },
+        Mode::Link => {
+            link(&file_paths, image_path, debug_info)?;
+            0
+        },
+        Mode::Build => {
+            build(&file_paths, options, image_path, debug_info)?;
+            0
+        },
+        Mode::Exec => match file_paths.as_slice() {
+            [image] => exec(image)?,
+            _ => return Err(anyhow!("expected single image file")),
+        },
+        Mode::Disassemble => {
+            for path in &file_paths {
+                print!("{}", disassemble(path)?);
+            }
+            0
+        },
+        Mode::Format => {
+            format_files(&file_paths)?;
+            0
+        },
+        Mode::Forth => run_forth(&file_paths)?,
+        Mode::TinyC => run_tinyc(&file_paths)?,
+        Mode::Brainfuck => run_brainfuck(&file_paths)?,
+        Mode::Check => {
+            check_stack(&file_paths, options)?;
+            0
+        },
+    };
     Ok(ExitCode::from(u8::try_from(rc)?))
 }
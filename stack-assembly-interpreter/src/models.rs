@@ -1,3 +1,7 @@
 pub mod token;
+pub mod expression;
 pub mod command;
 pub mod vm;
+pub mod object;
+pub mod error;
+pub mod lint;
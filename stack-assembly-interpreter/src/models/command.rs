@@ -13,9 +13,19 @@ pub struct Instruction {
 
 pub struct Command<'a> {
     pub mnemonics: &'a [&'a str],
+    /// Stack effect in Forth notation, e.g. `x y -- x+y`.
+    pub effect: &'a str,
     pub handler: &'static dyn CommandHandler,
 }
 
+impl Command<'_> {
+    /// Returns numbers of values popped and pushed according to declared stack effect.
+    pub fn arity(&self) -> (usize, usize) {
+        let (inputs, outputs) = self.effect.split_once("--").unwrap_or((self.effect, ""));
+        (inputs.split_whitespace().count(), outputs.split_whitespace().count())
+    }
+}
+
 pub type ReturnCode = i64;
 
 pub trait CommandHandler {
@@ -23,6 +33,7 @@ pub trait CommandHandler {
 }
 
 pub trait Input {
+    /// Returns next character code, -1 at end of input.
     fn get_char(&mut self) -> Result<i64>;
 }
 
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use anyhow::Result;
+
+use super::token::Position;
+
+/// Error at source position, with other positions related to it.
+#[derive(Debug)]
+pub struct SourceError {
+    pub position: Position,
+    pub message: String,
+    /// Positions with their descriptions, like `included from here`.
+    pub labels: Vec<(Position, String)>,
+    /// Name of denied lint which caused error.
+    pub lint: Option<&'static str>,
+    /// Details shown below rendered diagnostic, but not in message.
+    pub notes: Vec<String>,
+    cause: Option<anyhow::Error>,
+}
+
+impl SourceError {
+    pub fn new(position: Position, message: String) -> Self {
+        Self{position, message, labels: vec![], lint: None, notes: vec![], cause: None}
+    }
+
+    pub fn with_label(mut self, position: Position, label: &str) -> Self {
+        self.labels.push((position, label.to_string()));
+        self
+    }
+
+    pub fn with_lint(mut self, lint: &'static str) -> Self {
+        self.lint = Some(lint);
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn caused_by(mut self, cause: anyhow::Error) -> Self {
+        self.cause = Some(cause);
+        self
+    }
+
+    /// Returns source error on top of `err`, if there is one.
+    pub fn of(err: &anyhow::Error) -> Option<&SourceError> {
+        err.chain().next()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.lint {
+            Some(lint) => write!(f, "{}: error[{lint}]: {}", self.position, self.message)?,
+            None => write!(f, "{}: {}", self.position, self.message)?,
+        }
+        for (pos, label) in &self.labels {
+            write!(f, "\n    {label}: {pos}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SourceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.cause.as_ref().map(|x| x.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
+
+/// Wraps error into [`SourceError`], like [`anyhow::Context::with_context`].
+pub trait SourceContext<T> {
+    fn at_position<F: FnOnce() -> String>(self, pos: &Position, message: F) -> Result<T>;
+}
+
+impl<T> SourceContext<T> for Result<T> {
+    fn at_position<F: FnOnce() -> String>(self, pos: &Position, message: F) -> Result<T> {
+        self.map_err(|err| SourceError::new(pos.clone(), message()).caused_by(err).into())
+    }
+}
+
+/// Independent errors, shown one per line with their causes.
+#[derive(Debug)]
+pub struct Errors(pub Vec<anyhow::Error>);
+
+impl Errors {
+    /// Returns separate errors of `err`, looking into collected ones.
+    pub fn split(err: &anyhow::Error) -> Vec<&anyhow::Error> {
+        match err.chain().next().and_then(|x| x.downcast_ref::<Errors>()) {
+            Some(errors) => errors.0.iter().flat_map(Errors::split).collect(),
+            None => vec![err],
+        }
+    }
+}
+
+impl fmt::Display for Errors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, err) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{err:#}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Errors {}
+
+/// Collects all errors of iterator, not just the first one.
+pub trait CollectErrors<T> {
+    fn collect_errors<C: FromIterator<T>>(self) -> Result<C>;
+}
+
+impl<T, E: Into<anyhow::Error>, I: Iterator<Item = Result<T, E>>> CollectErrors<T> for I {
+    fn collect_errors<C: FromIterator<T>>(self) -> Result<C> {
+        let mut errors = vec![];
+        let res = self.filter_map(|x| x.map_err(|err| errors.push(err.into())).ok())
+                      .collect();
+        match errors.is_empty() {
+            true => Ok(res),
+            false => Err(Errors(errors).into()),
+        }
+    }
+}
+
+/// Creates [`SourceError`] at position, with formatted message.
+macro_rules! error_at {
+    ($pos:expr, $($arg:tt)*) => {
+        anyhow::Error::from($crate::models::error::SourceError::new(($pos).clone(), format!($($arg)*)))
+    };
+}
+
+/// Returns early with [`SourceError`] at position, with formatted message.
+macro_rules! bail_at {
+    ($pos:expr, $($arg:tt)*) => {
+        return Err($crate::models::error::error_at!($pos, $($arg)*))
+    };
+}
+
+pub(crate) use error_at;
+pub(crate) use bail_at;
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    fn pos(line: usize) -> Position {
+        Position{filename: "test".to_string(), line, column: 1, expansion: None}
+    }
+
+    #[test]
+    fn collects_all_errors() {
+        let got = [Ok(1), Err(error_at!(pos(1), "first")), Ok(2), Err(anyhow!("second")).context("outer")]
+            .into_iter()
+            .collect_errors::<Vec<_>>()
+            .unwrap_err();
+
+        assert_eq!(got.to_string(), "test:1:1: first\nouter: second");
+        assert_eq!(Errors::split(&got).iter().map(|x| SourceError::of(x).map(|x| x.position.line)).collect::<Vec<_>>(), [Some(1), None]);
+    }
+
+    #[test]
+    fn displays_labels_and_causes() {
+        let err = SourceError::new(pos(3), "message".to_string()).with_label(pos(1), "related")
+                                                                 .caused_by(anyhow!("cause"));
+
+        let got = anyhow::Error::from(err);
+
+        assert_eq!(format!("{got:#}"), "test:3:1: message\n    related: test:1:1: cause");
+    }
+}
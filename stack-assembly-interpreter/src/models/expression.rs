@@ -0,0 +1,68 @@
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Expression {
+    Integer(i64),
+    Symbol(String),
+    Negate(Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    pub fn symbols(&self) -> Vec<&String> {
+        match self {
+            Expression::Integer(_) => vec![],
+            Expression::Symbol(name) => vec![name],
+            Expression::Negate(x) => x.symbols(),
+            Expression::Binary(_, x, y) => {
+                let mut res = x.symbols();
+                res.extend(y.symbols());
+                res
+            },
+        }
+    }
+
+    pub fn symbols_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Expression::Integer(_) => vec![],
+            Expression::Symbol(name) => vec![name],
+            Expression::Negate(x) => x.symbols_mut(),
+            Expression::Binary(_, x, y) => {
+                let mut res = x.symbols_mut();
+                res.extend(y.symbols_mut());
+                res
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Sub => "-",
+            BinaryOperator::Mul => "*",
+            BinaryOperator::Div => "/",
+            BinaryOperator::Rem => "%",
+        };
+        write!(f, "{op}")
+    }
+}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Integer(i) => write!(f, "{i}"),
+            Expression::Symbol(name) => write!(f, "{name}"),
+            Expression::Negate(x) => write!(f, "-({x})"),
+            Expression::Binary(op, x, y) => write!(f, "({x}{op}{y})"),
+        }
+    }
+}
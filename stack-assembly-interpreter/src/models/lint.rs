@@ -0,0 +1,27 @@
+use super::token::Position;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+pub struct Lint {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: Level,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Warning {
+    pub lint: &'static str,
+    pub position: Position,
+    pub message: String,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: warning[{}]: {}", self.position, self.lint, self.message)
+    }
+}
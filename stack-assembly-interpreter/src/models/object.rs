@@ -0,0 +1,38 @@
+use super::command::Opcode;
+use super::expression::Expression;
+use super::token::{Position, Token};
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Operand {
+    Value(Opcode),
+    /// Value depends on addresses known only after linking.
+    Relocation(Expression),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ObjectInstruction {
+    pub operand: Operand,
+    pub token: Token,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum SymbolValue {
+    /// Label, offset of instruction from start of object.
+    Offset(i64),
+    Constant(i64),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: SymbolValue,
+    pub global: bool,
+    pub position: Position,
+}
+
+/// Relocatable translation of single source file.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Object {
+    pub instructions: Vec<ObjectInstruction>,
+    pub symbols: Vec<Symbol>,
+}
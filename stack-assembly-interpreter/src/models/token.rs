@@ -1,19 +1,82 @@
+use super::expression::Expression;
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum Token {
     Integer(i64, Position),
     Declaration(String, Position),
     Ident(String, Position),
+    Directive(String, Position),
+    String(String, Position),
+    /// `NAME EQU value` definition, doesn't occupy space in code segment.
+    Constant(String, i64, Position),
+    Expression(Expression, Position),
 }
 
-#[derive(PartialEq, Debug, Clone)]
+impl Token {
+    pub fn position(&self) -> &Position {
+        match self {
+            Token::Integer(_, pos) | Token::Declaration(_, pos) | Token::Ident(_, pos) | Token::Directive(_, pos)
+            | Token::String(_, pos) | Token::Constant(_, _, pos) | Token::Expression(_, pos) => pos,
+        }
+    }
+
+    pub fn with_position(self, pos: Position) -> Token {
+        match self {
+            Token::Integer(i, _) => Token::Integer(i, pos),
+            Token::Declaration(i, _) => Token::Declaration(i, pos),
+            Token::Ident(i, _) => Token::Ident(i, pos),
+            Token::Directive(i, _) => Token::Directive(i, pos),
+            Token::String(i, _) => Token::String(i, pos),
+            Token::Constant(i, value, _) => Token::Constant(i, value, pos),
+            Token::Expression(i, _) => Token::Expression(i, pos),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
 pub struct Position {
     pub filename: String,
     pub line: usize,
     pub column: usize,
+    /// Position inside a macro body, set for tokens produced by macro expansion.
+    /// In that case `filename`, `line` and `column` point at the invocation site.
+    pub expansion: Option<Box<Position>>,
+}
+
+impl Position {
+    pub fn same_line(&self, other: &Position) -> bool {
+        self.filename == other.filename && self.line == other.line && self.expansion == other.expansion
+    }
+
+    /// Returns position of token from macro body at `body`, expanded at `self`.
+    pub fn expanded(&self, body: &Position) -> Position {
+        let mut res = self.clone();
+        let mut last = &mut res.expansion;
+        while let Some(pos) = last {
+            last = &mut pos.expansion;
+        }
+        *last = Some(Box::new(body.clone()));
+        res
+    }
+
+    /// Returns position `offset` columns to the right in the source text, inside macro body if any.
+    pub fn shifted(&self, offset: usize) -> Position {
+        let mut res = self.clone();
+        let mut last = &mut res;
+        while last.expansion.is_some() {
+            last = last.expansion.as_mut().unwrap();
+        }
+        last.column += offset;
+        res
+    }
 }
 
 impl std::fmt::Display for Position {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}:{}", self.filename, self.line, self.column)
+        write!(f, "{}:{}:{}", self.filename, self.line, self.column)?;
+        if let Some(body) = &self.expansion {
+            write!(f, " (in macro body at {body})")?;
+        }
+        Ok(())
     }
 }
@@ -26,10 +26,12 @@ enum InternalAddress {
 impl VM {
     const MEM_SIZE: i64 = 1000*1000;
     const BANNED_SIZE: usize = 256;
+    /// Number of instructions that fit in memory.
+    pub const MAX_CODE_SIZE: usize = Self::MEM_SIZE as usize - Self::BANNED_SIZE;
 
     pub fn new(code: Vec<Instruction>) -> Self {
-        let actual_memory_size = (Self::MEM_SIZE as usize)-code.len()-Self::BANNED_SIZE;
-        let res = Self {
+        let actual_memory_size = Self::MAX_CODE_SIZE.saturating_sub(code.len());
+        Self {
             memory: vec![None; actual_memory_size],
             registers: Registers{
                 ip: 256,
@@ -38,8 +40,7 @@ impl VM {
                 rv: 0,
             },
             code,
-        };
-        res
+        }
     }
 
     pub fn registers(&self) -> &Registers {
@@ -71,17 +72,19 @@ impl VM {
                                                                 .ok_or_else(|| anyhow!("address too big"))?
                                                                 .ok_or_else(|| anyhow!("trying to read uninitialized memory")),
             }
-        })().context(anyhow!("invalid memory read at {i}"))
+        })().with_context(|| format!("invalid memory read at {i}"))
     }
 
     pub fn write_memory(&mut self, i: i64, data: Option<i64>) -> Result<()> {
         (|| {
             match self.get_internal_address(i)? {
                 InternalAddress::Code(_) => bail!("attempt to write at code segment"),
-                InternalAddress::Memory(internal) =>
-                    Ok(*self.memory.get_mut(internal).ok_or_else(|| anyhow!("address too big"))? = data),
+                InternalAddress::Memory(internal) => {
+                    *self.memory.get_mut(internal).ok_or_else(|| anyhow!("address too big"))? = data;
+                    Ok(())
+                },
             }
-        })().context(anyhow!("invalid memory write at {i}"))
+        })().with_context(|| format!("invalid memory write at {i}"))
     }
 
     pub fn read_stack(&self, offset: i64) -> Result<i64> {
@@ -89,7 +92,7 @@ impl VM {
     }
 
     pub fn read_code(&self, i: i64) -> Result<&Instruction> {
-        match self.get_internal_address(i).context(format!("failed to read code segment at {i}"))? {
+        match self.get_internal_address(i).with_context(|| format!("failed to read code segment at {i}"))? {
             InternalAddress::Code(internal) => Ok(&self.code[internal]),
             _ => Err(anyhow!("can't read instruction from memory segment at {i}"))
         }